@@ -1,22 +1,59 @@
 use anchor_lang::prelude::*;
+use anchor_lang::system_program::{create_account, CreateAccount};
 use anchor_spl::{
     associated_token::{
-        create as associatedTokenCreate, get_associated_token_address, AssociatedToken,
-        Create as CreateAssociate,
+        create as associatedTokenCreate, get_associated_token_address_with_program_id,
+        AssociatedToken, Create as CreateAssociate,
     },
     metadata::{
-        create_metadata_accounts_v3, mpl_token_metadata::types::DataV2, CreateMetadataAccountsV3,
-        Metadata as Metaplex,
+        create_master_edition_v3, create_metadata_accounts_v3,
+        mpl_token_metadata::types::{Collection, CollectionDetails, Creator, DataV2},
+        sign_metadata, update_metadata_accounts_v2, verify_sized_collection_item,
+        CreateMasterEditionV3, CreateMetadataAccountsV3, Metadata as Metaplex, MetadataAccount,
+        SignMetadata, UpdateMetadataAccountsV2, VerifySizedCollectionItem,
     },
-    token::{
-        mint_to, set_authority, spl_token, transfer, Mint, MintTo, SetAuthority, Token,
-        TokenAccount, Transfer as TokenTransfer,
+    token::Token,
+    token_interface::{
+        harvest_withheld_tokens_to_mint, initialize_mint2, metadata_pointer_initialize, mint_to,
+        set_authority,
+        spl_pod::optional_keys::OptionalNonZeroPubkey,
+        spl_token_2022::{
+            self,
+            extension::{
+                transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType,
+                StateWithExtensions,
+            },
+            state::Mint as MintState,
+        },
+        spl_token_metadata_interface::state::{Field, TokenMetadata},
+        token_metadata_initialize, token_metadata_update_authority, token_metadata_update_field,
+        transfer_checked, transfer_fee_initialize, withdraw_withheld_tokens_from_mint,
+        HarvestWithheldTokensToMint, InitializeMint2, MetadataPointerInitialize, Mint, MintTo,
+        SetAuthority, Token2022, TokenAccount, TokenInterface, TokenMetadataInitialize,
+        TokenMetadataUpdateAuthority, TokenMetadataUpdateField, TransferChecked,
+        TransferFeeInitialize, WithdrawWithheldTokensFromMint,
+    },
+};
+use solana_program::{
+    ed25519_program,
+    hash::hash,
+    system_instruction,
+    sysvar::instructions::{
+        self as instructions_sysvar, load_current_index_checked, load_instruction_at_checked,
     },
 };
-use solana_program::system_instruction;
 
 declare_id!("Ku6EPQycT3R2Y6PGy9cbooj9bNewKhVtMuzhhouomqX");
 
+/// `msg!` that is only compiled into debug builds, to keep log formatting
+/// out of the compute budget of release builds.
+macro_rules! debug_msg {
+    ($($arg:tt)*) => {
+        #[cfg(debug_assertions)]
+        msg!($($arg)*);
+    };
+}
+
 #[program]
 pub mod instapump {
     use super::*;
@@ -27,6 +64,12 @@ pub mod instapump {
         require!(!global.initialized, ProgramError::AlreadyInitialized);
         global.initialized = true;
         global.authority = ctx.accounts.user.key();
+        global.platforms[SocialPlatform::Instagram as usize].enabled = true;
+        global.version = GLOBAL_VERSION;
+        Ok(())
+    }
+
+    pub fn init_stats(_ctx: Context<InitStats>) -> Result<()> {
         Ok(())
     }
 
@@ -60,7 +103,7 @@ pub mod instapump {
         global.sol_share_first_buyer_after_raydium = sol_share_first_buyer_after_raydium;
         global.sol_share_instapump_after_raydium = sol_share_instapump_after_raydium;
 
-        emit!(SetParamsEvent {
+        emit_cpi!(SetParamsEvent {
             withdraw_authority,
             fee_recipient,
             initial_virtual_token_reserves,
@@ -74,27 +117,281 @@ pub mod instapump {
         Ok(())
     }
 
+    pub fn set_creator_initial_buy(
+        ctx: Context<SetParams>,
+        max_bps: u16,
+        sets_first_buyer: bool,
+    ) -> Result<()> {
+        require!(max_bps <= 10000, ProgramError::InvalidCreatorInitialBuyBps);
+
+        let global = &mut ctx.accounts.global;
+        global.max_creator_initial_buy_bps = max_bps;
+        global.creator_initial_buy_sets_first_buyer = sets_first_buyer;
+
+        emit_cpi!(SetCreatorInitialBuyEvent {
+            max_bps,
+            sets_first_buyer
+        });
+
+        Ok(())
+    }
+
+    pub fn set_graduation_target(
+        ctx: Context<SetParams>,
+        graduation_sol_target: u64,
+    ) -> Result<()> {
+        ctx.accounts.global.graduation_sol_target = graduation_sol_target;
+
+        emit_cpi!(SetGraduationTargetEvent {
+            graduation_sol_target
+        });
+
+        Ok(())
+    }
+
+    pub fn set_launch_window(
+        ctx: Context<SetParams>,
+        duration: u64,
+        in_slots: bool,
+        max_buy_per_wallet: u64,
+        fee_basis_points: u16,
+        fee_to_creator: bool,
+    ) -> Result<()> {
+        require!(
+            fee_basis_points <= 10000,
+            ProgramError::InvalidLaunchWindowFee
+        );
+
+        let global = &mut ctx.accounts.global;
+        global.launch_window_duration = duration;
+        global.launch_window_in_slots = in_slots;
+        global.launch_window_max_buy_per_wallet = max_buy_per_wallet;
+        global.launch_window_fee_basis_points = fee_basis_points;
+        global.launch_window_fee_to_creator = fee_to_creator;
+
+        emit_cpi!(SetLaunchWindowEvent {
+            duration,
+            in_slots,
+            max_buy_per_wallet,
+            fee_basis_points,
+            fee_to_creator
+        });
+
+        Ok(())
+    }
+
+    pub fn set_auction_params(ctx: Context<SetParams>, duration_seconds: u64) -> Result<()> {
+        let global = &mut ctx.accounts.global;
+        global.auction_duration_seconds = duration_seconds;
+
+        emit_cpi!(SetAuctionParamsEvent { duration_seconds });
+
+        Ok(())
+    }
+
+    pub fn set_transfer_fee_bounds(
+        ctx: Context<SetParams>,
+        max_basis_points: u16,
+        max_fee: u64,
+    ) -> Result<()> {
+        require!(max_basis_points <= 10000, ProgramError::InvalidTransferFee);
+
+        let global = &mut ctx.accounts.global;
+        global.max_transfer_fee_basis_points = max_basis_points;
+        global.max_transfer_fee = max_fee;
+
+        emit_cpi!(SetTransferFeeBoundsEvent {
+            max_basis_points,
+            max_fee
+        });
+
+        Ok(())
+    }
+
+    pub fn set_direct_launch_bounds(
+        ctx: Context<SetParams>,
+        min_sol: u64,
+        max_sol: u64,
+    ) -> Result<()> {
+        require!(min_sol <= max_sol, ProgramError::DirectLaunchSolOutOfBounds);
+
+        let global = &mut ctx.accounts.global;
+        global.direct_launch_min_sol = min_sol;
+        global.direct_launch_max_sol = max_sol;
+
+        emit_cpi!(SetDirectLaunchBoundsEvent { min_sol, max_sol });
+
+        Ok(())
+    }
+
+    pub fn set_metadata_update_limits(
+        ctx: Context<SetParams>,
+        window_seconds: u64,
+        max_trades: u64,
+    ) -> Result<()> {
+        let global = &mut ctx.accounts.global;
+        global.metadata_update_window_seconds = window_seconds;
+        global.metadata_update_max_trades = max_trades;
+
+        emit_cpi!(SetMetadataUpdateLimitsEvent {
+            window_seconds,
+            max_trades
+        });
+
+        Ok(())
+    }
+
+    pub fn set_platform_config(
+        ctx: Context<SetParams>,
+        platform: SocialPlatform,
+        enabled: bool,
+        mint_fee_sol: u64,
+    ) -> Result<()> {
+        let global = &mut ctx.accounts.global;
+        global.platforms[platform as usize] = PlatformConfig {
+            enabled,
+            mint_fee_sol,
+        };
+
+        emit_cpi!(SetPlatformConfigEvent {
+            platform,
+            enabled,
+            mint_fee_sol
+        });
+
+        Ok(())
+    }
+
+    pub fn set_attesters(
+        ctx: Context<SetParams>,
+        attesters: [Pubkey; MAX_ATTESTERS],
+    ) -> Result<()> {
+        let global = &mut ctx.accounts.global;
+        global.attesters = attesters;
+
+        emit_cpi!(SetAttestersEvent { attesters });
+
+        Ok(())
+    }
+
+    pub fn set_referral_tiers(
+        ctx: Context<SetParams>,
+        tier_basis_points: [u16; REFERRAL_TIERS],
+    ) -> Result<()> {
+        require!(
+            tier_basis_points.iter().all(|bps| *bps <= 10000),
+            ProgramError::InvalidReferralTier
+        );
+
+        let global = &mut ctx.accounts.global;
+        global.referral_tier_basis_points = tier_basis_points;
+
+        emit_cpi!(SetReferralTiersEvent { tier_basis_points });
+
+        Ok(())
+    }
+
     pub fn create(
         ctx: Context<Create>,
         name: String,
         symbol: String,
         uri: String,
+        platform: SocialPlatform,
         post_id: String,
-        direct_launch: bool,
+        args: CreateArgs,
     ) -> Result<()> {
-        msg!("Starting create function");
+        let CreateArgs {
+            post_id_hash,
+            metadata_hash,
+            initial_buy_amount,
+            max_sol_cost,
+            auction,
+            direct_launch,
+            direct_launch_sol,
+            transfer_fee_basis_points,
+            maximum_transfer_fee,
+            attestation_expiry,
+        } = args;
+        validate_metadata(&name, &symbol, &uri)?;
+
+        let platform_config = ctx.accounts.global.platforms[platform as usize];
+        require!(platform_config.enabled, ProgramError::PlatformDisabled);
+
+        // Variants of the same post must land on the same post record
+        let post_id = canonical_post_id(platform, &post_id)?;
+        require!(
+            post_id_hash == hash_post_id(&post_id),
+            ProgramError::PostIdHashMismatch
+        );
+
+        //////////////////////////////////////////
+        // START: Verify the attestation that the creator owns the post
+        //////////////////////////////////////////
+        if ctx.accounts.global.has_attesters() {
+            require!(
+                Clock::get()?.unix_timestamp <= attestation_expiry,
+                ProgramError::AttestationExpired
+            );
+            verify_attestation(
+                &ctx.accounts.instructions.to_account_info(),
+                &ctx.accounts.global.attesters,
+                &attestation_message(
+                    platform,
+                    &post_id_hash,
+                    &ctx.accounts.user.key(),
+                    &ctx.accounts.mint.key(),
+                    attestation_expiry,
+                ),
+            )?;
+        }
+        //////////////////////////////////////////
+        // END: Verify the attestation that the creator owns the post
+        //////////////////////////////////////////
+
+        // The post record is only created on first use, so a reused post ID
+        // is caught here instead of failing as "account already in use"
+        require!(
+            ctx.accounts.post_record.mint == Pubkey::default(),
+            ProgramError::PostIdAlreadyUsed
+        );
+        // Posts launched before post records existed only left an empty
+        // marker account, seeded with the post ID as it was given
+        if platform == SocialPlatform::Instagram {
+            if let Some((legacy_post, _)) = Pubkey::try_find_program_address(
+                &[b"instagram_post", post_id.as_bytes()],
+                ctx.program_id,
+            ) {
+                let legacy_post_account = ctx
+                    .accounts
+                    .legacy_post_account
+                    .as_ref()
+                    .ok_or(ProgramError::InvalidLegacyPostAccount)?;
+                require_keys_eq!(
+                    legacy_post_account.key(),
+                    legacy_post,
+                    ProgramError::InvalidLegacyPostAccount
+                );
+                require!(
+                    legacy_post_account.data_is_empty(),
+                    ProgramError::PostIdAlreadyUsed
+                );
+            }
+        }
+
         let bonding_curve = &mut ctx.accounts.bonding_curve;
-        msg!("Starting create function");
         let global = &ctx.accounts.global;
-        msg!("Starting create function");
 
         //////////////////////////////////////////
         // START: Transfer FEE from user to admin
         //////////////////////////////////////////
+        let mint_fee = global
+            .mint_fee_sol
+            .checked_add(platform_config.mint_fee_sol)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
         let ix = system_instruction::transfer(
             &ctx.accounts.user.key(),
             &ctx.accounts.fee_recipient.key(),
-            global.mint_fee_sol,
+            mint_fee,
         );
         anchor_lang::solana_program::program::invoke(
             &ix,
@@ -104,10 +401,11 @@ pub mod instapump {
                 ctx.accounts.system_program.to_account_info(),
             ],
         )?;
-        msg!("Complete : Transfer FEE from user to admin");
+        debug_msg!("Complete : Transfer FEE from user to admin");
         //////////////////////////////////////////
         // END: Transfer FEE from user to admin
         //////////////////////////////////////////
+        ctx.accounts.stats.record_launch(mint_fee)?;
 
         bonding_curve.mint = ctx.accounts.mint.key();
         bonding_curve.virtual_token_reserves = global.initial_virtual_token_reserves;
@@ -117,10 +415,54 @@ pub mod instapump {
         bonding_curve.token_total_supply = global.token_total_supply;
         bonding_curve.complete = false;
         bonding_curve.creator_address = ctx.accounts.user.key();
+        bonding_curve.graduation_sol_target = global.graduation_sol_target;
+        let clock = Clock::get()?;
+        bonding_curve.created_slot = clock.slot;
+        bonding_curve.created_at = clock.unix_timestamp;
+        bonding_curve.start_price_accumulator(clock.unix_timestamp);
+        if direct_launch {
+            require!(
+                global.direct_launch_max_sol > 0,
+                ProgramError::DirectLaunchDisabled
+            );
+            require!(
+                !auction && initial_buy_amount.is_none(),
+                ProgramError::DirectLaunchConflict
+            );
+            require!(
+                (global.direct_launch_min_sol..=global.direct_launch_max_sol)
+                    .contains(&direct_launch_sol),
+                ProgramError::DirectLaunchSolOutOfBounds
+            );
+        }
+        bonding_curve.post_record = ctx.accounts.post_record.key();
+        bonding_curve.platform = platform;
+        bonding_curve.version = BONDING_CURVE_VERSION;
+        bonding_curve.metadata_hash = metadata_hash.unwrap_or_default();
+        if auction {
+            require!(
+                global.auction_duration_seconds > 0,
+                ProgramError::AuctionDisabled
+            );
+            require!(
+                initial_buy_amount.is_none(),
+                ProgramError::InitialBuyDuringAuction
+            );
+            bonding_curve.phase = CurvePhase::Auction;
+            bonding_curve.auction_end_time = clock
+                .unix_timestamp
+                .saturating_add(global.auction_duration_seconds as i64);
+        }
+
+        let post_record = &mut ctx.accounts.post_record;
+        post_record.platform = platform;
+        post_record.post_id = post_id.clone();
+        post_record.mint = ctx.accounts.mint.key();
+        post_record.creator = ctx.accounts.user.key();
+        post_record.bonding_curve = ctx.accounts.bonding_curve.key();
+        post_record.created_slot = clock.slot;
+        post_record.status = LaunchStatus::Active;
 
-        //////////////////////////////////////////
-        // START: Mint Token to Bonding Curve
-        //////////////////////////////////////////
         let binding = ctx.accounts.mint.key();
         let seeds = &[
             "mint_authority".as_bytes(),
@@ -129,6 +471,150 @@ pub mod instapump {
         ];
         let signer = [&seeds[..]];
 
+        //////////////////////////////////////////
+        // START: Create the mint and its token accounts
+        //
+        // Token-2022 mints keep their metadata on the mint itself through the
+        // metadata-pointer and token-metadata extensions. Legacy SPL Token
+        // mints get a Metaplex metadata account further down instead.
+        //////////////////////////////////////////
+        let is_token_2022 = ctx.accounts.token_program.key() == Token2022::id();
+        let has_transfer_fee = transfer_fee_basis_points > 0;
+        if has_transfer_fee {
+            require!(is_token_2022, ProgramError::TransferFeeRequiresToken2022);
+            require!(
+                transfer_fee_basis_points <= global.max_transfer_fee_basis_points
+                    && maximum_transfer_fee <= global.max_transfer_fee,
+                ProgramError::InvalidTransferFee
+            );
+        }
+        let mut mint_extensions = vec![];
+        if is_token_2022 {
+            mint_extensions.push(ExtensionType::MetadataPointer);
+        }
+        if has_transfer_fee {
+            mint_extensions.push(ExtensionType::TransferFeeConfig);
+        }
+        let mint_space = ExtensionType::try_calculate_account_len::<MintState>(&mint_extensions)?;
+        // Initializing the token metadata grows the mint, so it is funded for
+        // its final size up front
+        let metadata_space = if is_token_2022 {
+            TokenMetadata {
+                update_authority: OptionalNonZeroPubkey(ctx.accounts.mint_authority.key()),
+                mint: binding,
+                name: name.clone(),
+                symbol: symbol.clone(),
+                uri: uri.clone(),
+                additional_metadata: vec![],
+            }
+            .tlv_size_of()?
+        } else {
+            0
+        };
+        create_account(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                CreateAccount {
+                    from: ctx.accounts.user.to_account_info(),
+                    to: ctx.accounts.mint.to_account_info(),
+                },
+            ),
+            ctx.accounts
+                .rent
+                .minimum_balance(mint_space + metadata_space),
+            mint_space as u64,
+            &ctx.accounts.token_program.key(),
+        )?;
+        if is_token_2022 {
+            metadata_pointer_initialize(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    MetadataPointerInitialize {
+                        token_program_id: ctx.accounts.token_program.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                    },
+                ),
+                Some(ctx.accounts.mint_authority.key()),
+                Some(binding),
+            )?;
+        }
+        if has_transfer_fee {
+            // The fee can never be changed, and only the program can withdraw
+            // what is withheld, on the creator's behalf
+            transfer_fee_initialize(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferFeeInitialize {
+                        token_program_id: ctx.accounts.token_program.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                    },
+                ),
+                None,
+                Some(&ctx.accounts.mint_authority.key()),
+                transfer_fee_basis_points,
+                maximum_transfer_fee,
+            )?;
+        }
+        initialize_mint2(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                InitializeMint2 {
+                    mint: ctx.accounts.mint.to_account_info(),
+                },
+            ),
+            TOKEN_DECIMALS,
+            &ctx.accounts.mint_authority.key(),
+            None,
+        )?;
+        if is_token_2022 {
+            token_metadata_initialize(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TokenMetadataInitialize {
+                        token_program_id: ctx.accounts.token_program.to_account_info(),
+                        metadata: ctx.accounts.mint.to_account_info(),
+                        update_authority: ctx.accounts.mint_authority.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        mint_authority: ctx.accounts.mint_authority.to_account_info(),
+                    },
+                    &signer,
+                ),
+                name.clone(),
+                symbol.clone(),
+                uri.clone(),
+            )?;
+        }
+
+        for (associated_token, authority) in [
+            (
+                ctx.accounts.associated_bonding_curve.to_account_info(),
+                ctx.accounts.bonding_curve.to_account_info(),
+            ),
+            (
+                ctx.accounts.associated_user.to_account_info(),
+                ctx.accounts.user.to_account_info(),
+            ),
+        ] {
+            associatedTokenCreate(CpiContext::new(
+                ctx.accounts.associated_token_program.to_account_info(),
+                CreateAssociate {
+                    payer: ctx.accounts.user.to_account_info(),
+                    associated_token,
+                    authority,
+                    mint: ctx.accounts.mint.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+            ))?;
+        }
+        debug_msg!("Complete : Create the mint and its token accounts");
+        //////////////////////////////////////////
+        // END: Create the mint and its token accounts
+        //////////////////////////////////////////
+
+        //////////////////////////////////////////
+        // START: Mint Token to Bonding Curve
+        //////////////////////////////////////////
         // Calculate token amounts
         let bonding_curve_amount = 1_000_000_000 * 1_000_000; // 1_000 million tokens with 6 decimal places
                                                               // let admin_amount: u64 = 200_000_000 * 1_000_000; // 200 million tokens with 6 decimal places
@@ -146,46 +632,123 @@ pub mod instapump {
             ),
             bonding_curve_amount,
         )?;
-        msg!("Complete : Mint Token to Bonding curve");
+        debug_msg!("Complete : Mint Token to Bonding curve");
         //////////////////////////////////////////
         // END: Mint Token to Bonding curve
         //////////////////////////////////////////
 
         //////////////////////////////////////////
         // START: Create Metadata - Information about this Token
+        //
+        // Only legacy SPL Token launches have Metaplex metadata, so only they
+        // join the collection. Token-2022 launches skip collection membership.
         //////////////////////////////////////////
-        let token_data = DataV2 {
-            name: name.clone(),
-            symbol: symbol.clone(),
-            uri: uri.clone(),
-            seller_fee_basis_points: 0,
-            creators: None,
-            collection: None,
-            uses: None,
-        };
+        if !is_token_2022 {
+            let token_metadata_program = ctx
+                .accounts
+                .token_metadata_program
+                .as_ref()
+                .ok_or(ProgramError::MetadataAccountsRequired)?;
+            let metadata = ctx
+                .accounts
+                .metadata
+                .as_ref()
+                .ok_or(ProgramError::MetadataAccountsRequired)?;
 
-        let metadata_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_metadata_program.to_account_info(),
-            CreateMetadataAccountsV3 {
-                metadata: ctx.accounts.metadata.to_account_info(),
-                mint: ctx.accounts.mint.to_account_info(),
-                mint_authority: ctx.accounts.mint_authority.to_account_info(),
-                payer: ctx.accounts.user.to_account_info(),
-                update_authority: ctx.accounts.mint_authority.to_account_info(),
-                system_program: ctx.accounts.system_program.to_account_info(),
-                rent: ctx.accounts.rent.to_account_info(),
-            },
-            &signer,
-        );
+            let collection_mint = ctx.accounts.global.collection_mint;
+            let has_collection = collection_mint != Pubkey::default();
+            let token_data = DataV2 {
+                name: name.clone(),
+                symbol: symbol.clone(),
+                uri: uri.clone(),
+                seller_fee_basis_points: 0,
+                creators: Some(vec![Creator {
+                    address: ctx.accounts.user.key(),
+                    verified: false,
+                    share: 100,
+                }]),
+                collection: has_collection.then_some(Collection {
+                    verified: false,
+                    key: collection_mint,
+                }),
+                uses: None,
+            };
 
-        create_metadata_accounts_v3(
-            metadata_ctx,
-            token_data,
-            false, // is_mutable
-            true,  // update_authority_is_signer
-            None,  // collection_details
-        )?;
-        msg!("Complete : Create Metadata - Information about this Token");
+            let metadata_ctx = CpiContext::new_with_signer(
+                token_metadata_program.to_account_info(),
+                CreateMetadataAccountsV3 {
+                    metadata: metadata.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    mint_authority: ctx.accounts.mint_authority.to_account_info(),
+                    payer: ctx.accounts.user.to_account_info(),
+                    update_authority: ctx.accounts.mint_authority.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    rent: ctx.accounts.rent.to_account_info(),
+                },
+                &signer,
+            );
+
+            create_metadata_accounts_v3(
+                metadata_ctx,
+                token_data,
+                true, // is_mutable, until the creator calls lock_metadata
+                true, // update_authority_is_signer
+                None, // collection_details
+            )?;
+
+            // The creator signs the create transaction, so it can verify
+            // itself in the creators array
+            sign_metadata(CpiContext::new(
+                token_metadata_program.to_account_info(),
+                SignMetadata {
+                    creator: ctx.accounts.user.to_account_info(),
+                    metadata: metadata.to_account_info(),
+                },
+            ))?;
+
+            if has_collection {
+                let collection_authority = ctx
+                    .accounts
+                    .collection_authority
+                    .as_ref()
+                    .ok_or(ProgramError::CollectionAccountsRequired)?;
+                let collection_bump = ctx
+                    .bumps
+                    .collection_authority
+                    .ok_or(ProgramError::CollectionAccountsRequired)?;
+                verify_sized_collection_item(
+                    CpiContext::new_with_signer(
+                        token_metadata_program.to_account_info(),
+                        VerifySizedCollectionItem {
+                            payer: ctx.accounts.user.to_account_info(),
+                            metadata: metadata.to_account_info(),
+                            collection_authority: collection_authority.to_account_info(),
+                            collection_mint: ctx
+                                .accounts
+                                .collection_mint
+                                .as_ref()
+                                .ok_or(ProgramError::CollectionAccountsRequired)?
+                                .to_account_info(),
+                            collection_metadata: ctx
+                                .accounts
+                                .collection_metadata
+                                .as_ref()
+                                .ok_or(ProgramError::CollectionAccountsRequired)?
+                                .to_account_info(),
+                            collection_master_edition: ctx
+                                .accounts
+                                .collection_master_edition
+                                .as_ref()
+                                .ok_or(ProgramError::CollectionAccountsRequired)?
+                                .to_account_info(),
+                        },
+                        &[&[b"collection_authority".as_ref(), &[collection_bump]]],
+                    ),
+                    None,
+                )?;
+            }
+            debug_msg!("Complete : Create Metadata - Information about this Token");
+        }
         //////////////////////////////////////////
         // END: Create Metadata - Information about this Token
         //////////////////////////////////////////
@@ -206,26 +769,210 @@ pub mod instapump {
                     &[ctx.bumps.mint_authority],
                 ]],
             ),
-            spl_token::instruction::AuthorityType::MintTokens,
+            spl_token_2022::instruction::AuthorityType::MintTokens,
             None,
         )?;
-        msg!("Complete : Disable Future minting");
+        debug_msg!("Complete : Disable Future minting");
         //////////////////////////////////////////
         // END: Disable Future minting
         //////////////////////////////////////////
 
         // Emit the creation event
-        emit!(CreateEvent {
+        emit_cpi!(CreateEvent {
             name,
             symbol,
             uri,
+            metadata_hash,
+            platform,
             post_id,
             mint: ctx.accounts.mint.key(),
             bonding_curve: ctx.accounts.bonding_curve.key(),
             user: ctx.accounts.user.key(),
-            direct_launch
+            direct_launch,
+            transfer_fee_basis_points,
+            maximum_transfer_fee
         });
 
+        //////////////////////////////////////////
+        // START: Direct launch, straight to migration
+        //
+        // The curve never trades: the whole supply stays in the bonding-curve
+        // until the withdraw authority moves it into the AMM pool. The
+        // creator's SOL is held in escrow next to it as the SOL side of the
+        // pool, and withdraw releases it only together with the tokens.
+        //////////////////////////////////////////
+        if direct_launch {
+            transfer_sol(
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.bonding_curve.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                direct_launch_sol,
+            )?;
+
+            let bonding_curve = &mut ctx.accounts.bonding_curve;
+            bonding_curve.real_sol_reserves = direct_launch_sol;
+            bonding_curve.direct_launch_escrow = direct_launch_sol;
+            bonding_curve.real_token_reserves = 0;
+            bonding_curve.first_buyer_address = ctx.accounts.user.key();
+            bonding_curve.complete = true;
+            ctx.accounts.stats.record_graduation()?;
+
+            emit_cpi!(CompleteEvent {
+                mint: ctx.accounts.mint.key(),
+                bonding_curve: bonding_curve.key(),
+                timestamp: clock.unix_timestamp,
+                virtual_sol_reserves: bonding_curve.virtual_sol_reserves,
+                virtual_token_reserves: bonding_curve.virtual_token_reserves,
+                real_sol_reserves: bonding_curve.real_sol_reserves,
+                real_token_reserves: bonding_curve.real_token_reserves,
+            });
+        }
+        //////////////////////////////////////////
+        // END: Direct launch, straight to migration
+        //////////////////////////////////////////
+
+        //////////////////////////////////////////
+        // START: Creator initial buy, in the same instruction as the launch
+        //////////////////////////////////////////
+        if let Some(initial_buy_amount) = initial_buy_amount {
+            let global = &ctx.accounts.global;
+            let max_initial_buy = (global.initial_real_token_reserves as u128
+                * global.max_creator_initial_buy_bps as u128
+                / 10000) as u64;
+            require!(
+                initial_buy_amount <= max_initial_buy,
+                ProgramError::CreatorInitialBuyTooLarge
+            );
+
+            let tokens_to_graduation = ctx.accounts.bonding_curve.tokens_to_graduation()?;
+            let completes = initial_buy_amount >= tokens_to_graduation;
+            let amount = initial_buy_amount.min(tokens_to_graduation);
+
+            let (price_per_token, sol_cost, new_virtual_token_reserves, new_virtual_sol_reserves) =
+                calculate_price_and_sol(
+                    amount,
+                    ctx.accounts.bonding_curve.virtual_token_reserves,
+                    ctx.accounts.bonding_curve.virtual_sol_reserves,
+                    ctx.accounts.bonding_curve.real_token_reserves,
+                    ctx.accounts.bonding_curve.real_sol_reserves,
+                )?;
+
+            // The creator fee would be paid to the creator themselves, so only
+            // the admin fee is charged on the initial buy
+            let admin_fee = (sol_cost * global.fee_basis_points as u64) / 10000;
+            let total_cost = sol_cost
+                .checked_add(admin_fee)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            require!(
+                total_cost <= max_sol_cost.unwrap_or(u64::MAX),
+                ProgramError::TooMuchSolRequired
+            );
+            transfer_sol(
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.fee_recipient.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                admin_fee,
+            )?;
+            transfer_sol(
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.bonding_curve.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                sol_cost,
+            )?;
+
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.associated_bonding_curve.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.associated_user.to_account_info(),
+                        authority: ctx.accounts.bonding_curve.to_account_info(),
+                    },
+                    &[&[
+                        b"bonding_curve",
+                        ctx.accounts.mint.key().as_ref(),
+                        &[ctx.bumps.bonding_curve],
+                    ]],
+                ),
+                amount,
+                TOKEN_DECIMALS,
+            )?;
+
+            let sets_first_buyer = global.creator_initial_buy_sets_first_buyer;
+            let bonding_curve = &mut ctx.accounts.bonding_curve;
+            bonding_curve.update_price_accumulator(Clock::get()?.unix_timestamp)?;
+            bonding_curve.apply_buy(
+                amount,
+                sol_cost,
+                new_virtual_token_reserves,
+                new_virtual_sol_reserves,
+            )?;
+            if sets_first_buyer {
+                bonding_curve.first_buyer_address = ctx.accounts.user.key();
+            }
+
+            let trade_sequence = bonding_curve.record_trade(true, sol_cost, amount, admin_fee)?;
+            bonding_curve.record_new_trader()?;
+            ctx.accounts.stats.record_trade(sol_cost, admin_fee)?;
+
+            if completes || bonding_curve.graduation_reached() {
+                bonding_curve.complete = true;
+                ctx.accounts.stats.record_graduation()?;
+                emit_cpi!(CompleteEvent {
+                    mint: ctx.accounts.mint.key(),
+                    bonding_curve: bonding_curve.key(),
+                    timestamp: Clock::get()?.unix_timestamp,
+                    virtual_sol_reserves: bonding_curve.virtual_sol_reserves,
+                    virtual_token_reserves: bonding_curve.virtual_token_reserves,
+                    real_sol_reserves: bonding_curve.real_sol_reserves,
+                    real_token_reserves: bonding_curve.real_token_reserves,
+                });
+            }
+
+            let creator_position = &mut ctx.accounts.creator_position;
+            creator_position.bonding_curve = ctx.accounts.bonding_curve.key();
+            creator_position.user = ctx.accounts.user.key();
+            creator_position.record_buy(
+                amount,
+                sol_cost,
+                admin_fee,
+                Clock::get()?.unix_timestamp,
+            )?;
+
+            let clock = Clock::get()?;
+            let tokens_received =
+                amount - transfer_fee(&ctx.accounts.mint.to_account_info(), clock.epoch, amount)?;
+            emit_cpi!(TradeEvent {
+                version: TRADE_EVENT_VERSION,
+                mint: ctx.accounts.mint.key(),
+                bonding_curve: ctx.accounts.bonding_curve.key(),
+                creator: ctx.accounts.user.key(),
+                sol_amount: sol_cost,
+                token_amount: amount,
+                tokens_received,
+                price_per_token,
+                is_buy: true,
+                is_creator_buy: true,
+                user: ctx.accounts.user.key(),
+                recipient: ctx.accounts.user.key(),
+                admin_fee,
+                creator_fee: 0,
+                launch_window_fee: 0,
+                referrer: Pubkey::default(),
+                referrer_fee: 0,
+                trade_sequence,
+                timestamp: clock.unix_timestamp,
+                virtual_sol_reserves: new_virtual_sol_reserves,
+                virtual_token_reserves: new_virtual_token_reserves,
+                real_sol_reserves: ctx.accounts.bonding_curve.real_sol_reserves,
+                real_token_reserves: ctx.accounts.bonding_curve.real_token_reserves,
+            });
+        }
+        //////////////////////////////////////////
+        // END: Creator initial buy
+        //////////////////////////////////////////
+
         Ok(())
     }
 
@@ -233,7 +980,10 @@ pub mod instapump {
         // Extract necessary information before mutable borrow
         let bonding_curve_key = ctx.accounts.bonding_curve.key();
         let user_key = ctx.accounts.user.key();
-        let fee_recipient_key = ctx.accounts.fee_recipient.key();
+        let recipient = match &ctx.accounts.recipient {
+            Some(recipient) => recipient.to_account_info(),
+            None => ctx.accounts.user.to_account_info(),
+        };
 
         let global = &ctx.accounts.global;
 
@@ -242,6 +992,34 @@ pub mod instapump {
             !ctx.accounts.bonding_curve.complete,
             ProgramError::BondingCurveComplete
         );
+        require!(
+            ctx.accounts.bonding_curve.phase == CurvePhase::Trading,
+            ProgramError::AuctionInProgress
+        );
+        // Once a curve has a price history, every trade must update it
+        require!(
+            ctx.accounts.price_history.is_some()
+                || !ctx.accounts.bonding_curve.price_history_initialized,
+            ProgramError::PriceHistoryRequired
+        );
+        require_keys_eq!(
+            ctx.accounts.associated_user.key(),
+            get_associated_token_address_with_program_id(
+                &recipient.key(),
+                &ctx.accounts.mint.key(),
+                &ctx.accounts.token_program.key()
+            ),
+            ProgramError::InvalidRecipientTokenAccount
+        );
+
+        let clock = Clock::get()?;
+        let price_before = ctx.accounts.bonding_curve.spot_price();
+
+        // Clamp the purchase to what is left before graduation, so the last buyer
+        // doesn't have to guess the exact remainder
+        let tokens_to_graduation = ctx.accounts.bonding_curve.tokens_to_graduation()?;
+        let completes = amount >= tokens_to_graduation;
+        let amount = amount.min(tokens_to_graduation);
 
         // Calculate the SOL cost for the purchase
         let (price_per_token, sol_cost, new_virtual_token_reserves, new_virtual_sol_reserves) =
@@ -252,68 +1030,129 @@ pub mod instapump {
                 ctx.accounts.bonding_curve.real_token_reserves,
                 ctx.accounts.bonding_curve.real_sol_reserves,
             )?;
-        msg!("buy() amount: {:?} & sol_cost: {:?}", amount, sol_cost);
-        // Check if the SOL cost is within the user's specified limit
-        require!(sol_cost <= max_sol_cost, ProgramError::TooMuchSolRequired);
+        debug_msg!("buy() amount: {:?} & sol_cost: {:?}", amount, sol_cost);
 
         // Calculate fee
         let admin_fee = (sol_cost * global.fee_basis_points as u64) / 10000;
         let creator_fee = (sol_cost * global.trading_fee_creator_percent_sol as u64) / 10000;
+
+        // Carve the referrer's share out of the admin fee
+        let (referrer_key, referrer_fee) = match &ctx.accounts.referrer {
+            Some(referrer) => {
+                require!(referrer.authority != user_key, ProgramError::SelfReferral);
+                (
+                    referrer.key(),
+                    ctx.accounts.global.referral_fee(admin_fee, referrer.tier),
+                )
+            }
+            None => (Pubkey::default(), 0),
+        };
+        let admin_fee = admin_fee - referrer_fee;
+
         //////////////////////////////////////////
-        // START: Transfer 1% FEE from user to admin
+        // START: Launch window per-wallet cap and decaying fee
         //////////////////////////////////////////
-        let ix = system_instruction::transfer(
-            &ctx.accounts.user.key(),
-            &ctx.accounts.fee_recipient.key(),
-            admin_fee,
-        );
-        anchor_lang::solana_program::program::invoke(
-            &ix,
-            &[
-                ctx.accounts.user.to_account_info(),
-                ctx.accounts.fee_recipient.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-        )?;
+        let launch_window_remaining = ctx
+            .accounts
+            .bonding_curve
+            .launch_window_remaining(global, &clock);
+        let launch_window_fee = match launch_window_remaining {
+            Some(remaining) => {
+                // Every buy of the wallet so far happened inside the window
+                let wallet_bought = ctx
+                    .accounts
+                    .user_position
+                    .tokens_bought
+                    .checked_add(amount)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+                require!(
+                    global.launch_window_max_buy_per_wallet == 0
+                        || wallet_bought <= global.launch_window_max_buy_per_wallet,
+                    ProgramError::LaunchWindowBuyCapExceeded
+                );
+                global.launch_window_fee(sol_cost, remaining)
+            }
+            None => 0,
+        };
         //////////////////////////////////////////
-        // END: Transfer 1% FEE from user to admin
+        // END: Launch window per-wallet cap and decaying fee
         //////////////////////////////////////////
 
+        // Check if the SOL cost, fees included, is within the user's specified limit
+        let total_cost = [admin_fee, creator_fee, referrer_fee, launch_window_fee]
+            .into_iter()
+            .try_fold(sol_cost, u64::checked_add)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        require!(total_cost <= max_sol_cost, ProgramError::TooMuchSolRequired);
+
         //////////////////////////////////////////
-        // START: Transfer {trading_fee_creator_percent_sol}% FEE from user to creator
+        // START: Transfer SOL and fees from user to bonding-curve
+        //
+        // A single system transfer moves the cost and every fee into the
+        // bonding-curve, which is owned by this program and can then pay the
+        // fee recipients by moving lamports directly, without more CPIs.
         //////////////////////////////////////////
-        let ix = system_instruction::transfer(
-            &ctx.accounts.user.key(),
-            &ctx.accounts.creator.key(),
+        transfer_sol(
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.bonding_curve.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            total_cost,
+        )?;
+
+        let bonding_curve_info = ctx.accounts.bonding_curve.to_account_info();
+        let launch_window_fee_recipient = if global.launch_window_fee_to_creator {
+            ctx.accounts.creator.to_account_info()
+        } else {
+            ctx.accounts.fee_recipient.to_account_info()
+        };
+        move_lamports(
+            &bonding_curve_info,
+            &ctx.accounts.fee_recipient.to_account_info(),
+            admin_fee,
+        )?;
+        move_lamports(
+            &bonding_curve_info,
+            &ctx.accounts.creator.to_account_info(),
             creator_fee,
-        );
-        anchor_lang::solana_program::program::invoke(
-            &ix,
-            &[
-                ctx.accounts.user.to_account_info(),
-                ctx.accounts.creator.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
         )?;
+        move_lamports(
+            &bonding_curve_info,
+            &launch_window_fee_recipient,
+            launch_window_fee,
+        )?;
+        if let Some(referrer) = &mut ctx.accounts.referrer {
+            move_lamports(
+                &bonding_curve_info,
+                &referrer.to_account_info(),
+                referrer_fee,
+            )?;
+            referrer.total_earned = referrer
+                .total_earned
+                .checked_add(referrer_fee)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
         //////////////////////////////////////////
-        // END: Transfer {trading_fee_creator_percent_sol}% FEE from user to creator
+        // END: Transfer SOL and fees from user to bonding-curve
         //////////////////////////////////////////
 
         //////////////////////////////////////////
-        // START: Transfer SOL from user to bonding-curve
+        // START: Create the recipient's token account, paid by the buyer
         //////////////////////////////////////////
-        let transfer_to_bonding_curve_ix =
-            system_instruction::transfer(&user_key, &bonding_curve_key, sol_cost);
-        anchor_lang::solana_program::program::invoke(
-            &transfer_to_bonding_curve_ix,
-            &[
-                ctx.accounts.user.to_account_info(),
-                ctx.accounts.bonding_curve.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-        )?;
+        if ctx.accounts.associated_user.data_is_empty() {
+            associatedTokenCreate(CpiContext::new(
+                ctx.accounts.associated_token_program.to_account_info(),
+                CreateAssociate {
+                    payer: ctx.accounts.user.to_account_info(),
+                    associated_token: ctx.accounts.associated_user.to_account_info(),
+                    authority: recipient.clone(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+            ))?;
+        }
         //////////////////////////////////////////
-        // END: Transfer SOL from user to bonding-curve
+        // END: Create the recipient's token account, paid by the buyer
         //////////////////////////////////////////
 
         //////////////////////////////////////////
@@ -327,38 +1166,29 @@ pub mod instapump {
         ];
         let signer = &[&seeds[..]];
 
-        let cpi_accounts = TokenTransfer {
+        let cpi_accounts = TransferChecked {
             from: ctx.accounts.associated_bonding_curve.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
             to: ctx.accounts.associated_user.to_account_info(),
             authority: ctx.accounts.bonding_curve.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
 
-        transfer(cpi_ctx, amount)?;
+        transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
         //////////////////////////////////////////
         // END: Transfer Tokens from bonding-curve to user
         //////////////////////////////////////////
 
-        let new_real_token_reserves = ctx
-            .accounts
-            .bonding_curve
-            .real_token_reserves
-            .checked_sub(amount)
-            .unwrap();
-        let new_real_sol_reserves = ctx
-            .accounts
-            .bonding_curve
-            .real_sol_reserves
-            .checked_add(sol_cost)
-            .unwrap();
-
         // Update bonding curve state
         let bonding_curve = &mut ctx.accounts.bonding_curve;
-        bonding_curve.virtual_token_reserves = new_virtual_token_reserves;
-        bonding_curve.virtual_sol_reserves = new_virtual_sol_reserves;
-        bonding_curve.real_token_reserves = new_real_token_reserves;
-        bonding_curve.real_sol_reserves = new_real_sol_reserves;
+        bonding_curve.update_price_accumulator(clock.unix_timestamp)?;
+        bonding_curve.apply_buy(
+            amount,
+            sol_cost,
+            new_virtual_token_reserves,
+            new_virtual_sol_reserves,
+        )?;
         //////////////////////////////////////////
         // END: Update quantity tracking variables
         //////////////////////////////////////////
@@ -376,28 +1206,96 @@ pub mod instapump {
         //////////////////////////////////////////
         // START: Set bonding-curve to Complete; if it is complete
         //////////////////////////////////////////
-        if (bonding_curve.real_token_reserves == 0) {
+        if completes || bonding_curve.graduation_reached() {
             bonding_curve.complete = true;
-            emit!(CompleteEvent {
+            ctx.accounts.stats.record_graduation()?;
+            emit_cpi!(CompleteEvent {
                 mint: ctx.accounts.mint.key(),
                 bonding_curve: bonding_curve.key(),
-                timestamp: Clock::get()?.unix_timestamp,
+                timestamp: clock.unix_timestamp,
+                virtual_sol_reserves: bonding_curve.virtual_sol_reserves,
+                virtual_token_reserves: bonding_curve.virtual_token_reserves,
+                real_sol_reserves: bonding_curve.real_sol_reserves,
+                real_token_reserves: bonding_curve.real_token_reserves,
             });
         }
         //////////////////////////////////////////
         // END: Set bonding-curve to Complete
         //////////////////////////////////////////
 
+        //////////////////////////////////////////
+        // START: Update user position
+        //////////////////////////////////////////
+        let total_fees = admin_fee + referrer_fee + creator_fee + launch_window_fee;
+        let user_position = &mut ctx.accounts.user_position;
+        let is_new_trader = user_position.first_trade_at == 0;
+        user_position.bonding_curve = bonding_curve_key;
+        user_position.user = recipient.key();
+        user_position.record_buy(amount, sol_cost, total_fees, clock.unix_timestamp)?;
+        //////////////////////////////////////////
+        // END: Update user position
+        //////////////////////////////////////////
+
+        //////////////////////////////////////////
+        // START: Update statistics
+        //////////////////////////////////////////
+        let trade_sequence = bonding_curve.record_trade(true, sol_cost, amount, total_fees)?;
+        if is_new_trader {
+            bonding_curve.record_new_trader()?;
+        }
+        let protocol_fees = if global.launch_window_fee_to_creator {
+            admin_fee
+        } else {
+            admin_fee + launch_window_fee
+        };
+        ctx.accounts.stats.record_trade(sol_cost, protocol_fees)?;
+        //////////////////////////////////////////
+        // END: Update statistics
+        //////////////////////////////////////////
+
+        //////////////////////////////////////////
+        // START: Update price history candles
+        //////////////////////////////////////////
+        if let Some(price_history) = &mut ctx.accounts.price_history {
+            price_history.record_trade(
+                clock.unix_timestamp,
+                price_before,
+                bonding_curve.spot_price(),
+                sol_cost,
+                amount,
+            );
+        }
+        //////////////////////////////////////////
+        // END: Update price history candles
+        //////////////////////////////////////////
+
         // Emit the trade event
-        emit!(TradeEvent {
+        let tokens_received =
+            amount - transfer_fee(&ctx.accounts.mint.to_account_info(), clock.epoch, amount)?;
+        emit_cpi!(TradeEvent {
+            version: TRADE_EVENT_VERSION,
             mint: ctx.accounts.mint.key(),
+            bonding_curve: bonding_curve_key,
+            creator: bonding_curve.creator_address,
             sol_amount: sol_cost,
             token_amount: amount,
+            tokens_received,
+            price_per_token,
             is_buy: true,
+            is_creator_buy: user_key == bonding_curve.creator_address,
             user: user_key,
-            timestamp: Clock::get()?.unix_timestamp,
+            recipient: recipient.key(),
+            admin_fee,
+            creator_fee,
+            launch_window_fee,
+            referrer: referrer_key,
+            referrer_fee,
+            trade_sequence,
+            timestamp: clock.unix_timestamp,
             virtual_sol_reserves: new_virtual_sol_reserves,
             virtual_token_reserves: new_virtual_token_reserves,
+            real_sol_reserves: bonding_curve.real_sol_reserves,
+            real_token_reserves: bonding_curve.real_token_reserves,
         });
 
         Ok(())
@@ -405,7 +1303,6 @@ pub mod instapump {
     pub fn sell(ctx: Context<Sell>, amount: u64, min_sol_output: u64) -> Result<()> {
         let bonding_curve_key = ctx.accounts.bonding_curve.key();
         let user_key = ctx.accounts.user.key();
-        let fee_recipient_key = ctx.accounts.fee_recipient.key();
         let mint_key = ctx.accounts.mint.key();
 
         // Check if the bonding curve is complete
@@ -413,81 +1310,112 @@ pub mod instapump {
             !ctx.accounts.bonding_curve.complete,
             ProgramError::BondingCurveComplete
         );
+        require!(
+            ctx.accounts.bonding_curve.phase == CurvePhase::Trading,
+            ProgramError::AuctionInProgress
+        );
+        // Once a curve has a price history, every trade must update it
+        require!(
+            ctx.accounts.price_history.is_some()
+                || !ctx.accounts.bonding_curve.price_history_initialized,
+            ProgramError::PriceHistoryRequired
+        );
+
+        let clock = Clock::get()?;
+        let price_before = ctx.accounts.bonding_curve.spot_price();
+
+        // A Token-2022 transfer fee is withheld from the tokens sent, so the
+        // curve is priced on what it actually receives
+        let tokens_received =
+            amount - transfer_fee(&ctx.accounts.mint.to_account_info(), clock.epoch, amount)?;
 
         // Calculate values
         let (price_per_token, sol_output, new_virtual_token_reserves, new_virtual_sol_reserves) =
             calculate_price_and_sol_sell_operation(
-                amount,
+                tokens_received,
                 ctx.accounts.bonding_curve.virtual_token_reserves,
                 ctx.accounts.bonding_curve.virtual_sol_reserves,
                 ctx.accounts.bonding_curve.real_token_reserves,
                 ctx.accounts.bonding_curve.real_sol_reserves,
             )?;
-        msg!("sell() amount: {:?} & sol_cost: {:?}", amount, sol_output);
+        debug_msg!("sell() amount: {:?} & sol_cost: {:?}", amount, sol_output);
         // require!(sol_output >= min_sol_output, ProgramError::SlippageExceeded);
 
         // Calculate fee
         let admin_fee = (sol_output * ctx.accounts.global.fee_basis_points as u64) / 10000;
         let creator_fee =
             (sol_output * ctx.accounts.global.trading_fee_creator_percent_sol as u64) / 10000;
-        //////////////////////////////////////////
-        // START: Transfer 1% FEE from user to admin
-        //////////////////////////////////////////
-        let ix = system_instruction::transfer(
-            &ctx.accounts.user.key(),
-            &ctx.accounts.fee_recipient.key(),
-            admin_fee,
-        );
-        anchor_lang::solana_program::program::invoke(
-            &ix,
-            &[
-                ctx.accounts.user.to_account_info(),
-                ctx.accounts.fee_recipient.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-        )?;
-        //////////////////////////////////////////
-        // END: Transfer 1% FEE from user to admin
-        //////////////////////////////////////////
 
-        //////////////////////////////////////////
-        // START: Transfer {trading_fee_creator_percent_sol}% FEE from user to creator
-        //////////////////////////////////////////
-        let ix = system_instruction::transfer(
-            &ctx.accounts.user.key(),
-            &ctx.accounts.creator.key(),
-            creator_fee,
-        );
-        anchor_lang::solana_program::program::invoke(
-            &ix,
-            &[
-                ctx.accounts.user.to_account_info(),
-                ctx.accounts.creator.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-        )?;
-        //////////////////////////////////////////
-        // END: Transfer {trading_fee_creator_percent_sol}% FEE from user to creator
-        //////////////////////////////////////////
+        // Carve the referrer's share out of the admin fee
+        let (referrer_key, referrer_fee) = match &ctx.accounts.referrer {
+            Some(referrer) => {
+                require!(referrer.authority != user_key, ProgramError::SelfReferral);
+                (
+                    referrer.key(),
+                    ctx.accounts.global.referral_fee(admin_fee, referrer.tier),
+                )
+            }
+            None => (Pubkey::default(), 0),
+        };
+        let admin_fee = admin_fee - referrer_fee;
 
         // Perform transfers
-        transfer(
+        transfer_checked(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
-                TokenTransfer {
+                TransferChecked {
                     from: ctx.accounts.associated_user.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
                     to: ctx.accounts.associated_bonding_curve.to_account_info(),
                     authority: ctx.accounts.user.to_account_info(),
                 },
             ),
             amount,
+            ctx.accounts.mint.decimals,
         )?;
 
-        let bonding_curve = &mut ctx.accounts.bonding_curve;
+        //////////////////////////////////////////
+        // START: Transfer SOL from bonding-curve to user and fee recipients
+        //
+        // Fees are deducted from the proceeds and paid straight out of the
+        // bonding-curve, so a sell needs no system transfers at all.
+        //////////////////////////////////////////
+        let total_fees = admin_fee + referrer_fee + creator_fee;
+        let bonding_curve_info = ctx.accounts.bonding_curve.to_account_info();
+        move_lamports(
+            &bonding_curve_info,
+            &ctx.accounts.user.to_account_info(),
+            sol_output
+                .checked_sub(total_fees)
+                .ok_or(ProgramError::InsufficientFunds)?,
+        )?;
+        move_lamports(
+            &bonding_curve_info,
+            &ctx.accounts.fee_recipient.to_account_info(),
+            admin_fee,
+        )?;
+        move_lamports(
+            &bonding_curve_info,
+            &ctx.accounts.creator.to_account_info(),
+            creator_fee,
+        )?;
+        if let Some(referrer) = &mut ctx.accounts.referrer {
+            move_lamports(
+                &bonding_curve_info,
+                &referrer.to_account_info(),
+                referrer_fee,
+            )?;
+            referrer.total_earned = referrer
+                .total_earned
+                .checked_add(referrer_fee)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+        //////////////////////////////////////////
+        // END: Transfer SOL from bonding-curve to user and fee recipients
+        //////////////////////////////////////////
 
-        // Transfer SOL from bonding curve to user
-        **bonding_curve.to_account_info().try_borrow_mut_lamports()? -= sol_output;
-        **ctx.accounts.user.try_borrow_mut_lamports()? += sol_output;
+        let bonding_curve = &mut ctx.accounts.bonding_curve;
+        bonding_curve.update_price_accumulator(clock.unix_timestamp)?;
 
         //////////////////////////////////////////
         // START: Update quantity tracking variables
@@ -514,7 +1442,7 @@ pub mod instapump {
 
         bonding_curve.real_token_reserves = bonding_curve
             .real_token_reserves
-            .checked_add(amount)
+            .checked_add(tokens_received)
             .ok_or(ProgramError::ArithmeticOverflow)?;
 
         bonding_curve.real_sol_reserves = bonding_curve
@@ -525,16 +1453,72 @@ pub mod instapump {
         // END: Update quantity tracking variables
         //////////////////////////////////////////
 
+        //////////////////////////////////////////
+        // START: Update user position
+        //////////////////////////////////////////
+        let user_position = &mut ctx.accounts.user_position;
+        let is_new_trader = user_position.first_trade_at == 0;
+        user_position.bonding_curve = bonding_curve_key;
+        user_position.user = user_key;
+        user_position.record_sell(amount, sol_output, total_fees, clock.unix_timestamp)?;
+        //////////////////////////////////////////
+        // END: Update user position
+        //////////////////////////////////////////
+
+        //////////////////////////////////////////
+        // START: Update statistics
+        //////////////////////////////////////////
+        let trade_sequence =
+            bonding_curve.record_trade(false, sol_output, tokens_received, total_fees)?;
+        if is_new_trader {
+            bonding_curve.record_new_trader()?;
+        }
+        ctx.accounts.stats.record_trade(sol_output, admin_fee)?;
+        //////////////////////////////////////////
+        // END: Update statistics
+        //////////////////////////////////////////
+
+        //////////////////////////////////////////
+        // START: Update price history candles
+        //////////////////////////////////////////
+        if let Some(price_history) = &mut ctx.accounts.price_history {
+            price_history.record_trade(
+                clock.unix_timestamp,
+                price_before,
+                bonding_curve.spot_price(),
+                sol_output,
+                tokens_received,
+            );
+        }
+        //////////////////////////////////////////
+        // END: Update price history candles
+        //////////////////////////////////////////
+
         // Emit the trade event
-        emit!(TradeEvent {
+        emit_cpi!(TradeEvent {
+            version: TRADE_EVENT_VERSION,
             mint: ctx.accounts.mint.key(),
+            bonding_curve: bonding_curve_key,
+            creator: bonding_curve.creator_address,
             sol_amount: sol_output,
             token_amount: amount,
+            tokens_received,
+            price_per_token,
             is_buy: false,
+            is_creator_buy: false,
             user: user_key,
-            timestamp: Clock::get()?.unix_timestamp,
+            recipient: user_key,
+            admin_fee,
+            creator_fee,
+            launch_window_fee: 0,
+            referrer: referrer_key,
+            referrer_fee,
+            trade_sequence,
+            timestamp: clock.unix_timestamp,
             virtual_sol_reserves: bonding_curve.virtual_sol_reserves,
             virtual_token_reserves: bonding_curve.virtual_token_reserves,
+            real_sol_reserves: bonding_curve.real_sol_reserves,
+            real_token_reserves: bonding_curve.real_token_reserves,
         });
         Ok(())
     }
@@ -553,20 +1537,44 @@ pub mod instapump {
         let minimum_balance = rent.minimum_balance(8 + BondingCurve::LEN);
 
         // calculate token withdraw metrics
-        let current_token_balance = ctx.accounts.associated_bonding_curve.amount;
+        // Tokens and refunds still owed to auction bidders stay in the curve
+        let current_token_balance = ctx
+            .accounts
+            .associated_bonding_curve
+            .amount
+            .saturating_sub(ctx.accounts.bonding_curve.auction_unclaimed_tokens);
         let creator_token_share =
             (current_token_balance * global.token_share_creator_percent as u64) / 10000;
         let token_amount_admin_withdraw = current_token_balance.saturating_sub(creator_token_share);
 
+        // A direct launch deposit is the SOL side of the pool: it goes out
+        // with the tokens and pays no shares
+        let direct_launch_sol = ctx.accounts.bonding_curve.direct_launch_escrow;
+        let (first_buyer_sol_share, instapump_sol_share) = if direct_launch_sol > 0 {
+            (0, 0)
+        } else {
+            (
+                global.sol_share_first_buyer_after_raydium,
+                global.sol_share_instapump_after_raydium,
+            )
+        };
+
         // Calculate the amount to withdraw, ensuring we leave enough for rent exemption
-        let current_sol_balance = ctx.accounts.bonding_curve.to_account_info().lamports();
-        let sol_amount = current_sol_balance.saturating_sub(minimum_balance + 10000);
-        let sol_amount_admin_withdraw = sol_amount
-            .saturating_sub(global.sol_share_first_buyer_after_raydium)
-            .saturating_sub(global.sol_share_instapump_after_raydium);
-
-        msg!("withdraw() current_sol_balance: {:?}", current_sol_balance);
-        msg!(
+        let current_sol_balance = ctx
+            .accounts
+            .bonding_curve
+            .to_account_info()
+            .lamports()
+            .saturating_sub(ctx.accounts.bonding_curve.auction_unclaimed_refunds);
+        let sol_amount_admin_withdraw = withdraw_sol_amount(
+            current_sol_balance,
+            minimum_balance,
+            first_buyer_sol_share,
+            instapump_sol_share,
+        );
+
+        debug_msg!("withdraw() current_sol_balance: {:?}", current_sol_balance);
+        debug_msg!(
             "withdraw() sol_amount_admin_withdraw: {:?}",
             sol_amount_admin_withdraw
         );
@@ -585,19 +1593,21 @@ pub mod instapump {
         //////////////////////////////////////////
         // START: Transfer Token share creator (token_share_creator_percent)
         //////////////////////////////////////////
-        transfer(
+        transfer_checked(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
-                TokenTransfer {
+                TransferChecked {
                     from: ctx.accounts.associated_bonding_curve.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
                     to: ctx.accounts.creator_associated_user.to_account_info(),
                     authority: ctx.accounts.bonding_curve.to_account_info(),
                 },
                 signer,
             ),
             creator_token_share,
+            ctx.accounts.mint.decimals,
         )?;
-        msg!("Token transferred to admin");
+        debug_msg!("Token transferred to admin");
         //////////////////////////////////////////
         // END: Transfer Token share creator (token_share_creator_percent)
         //////////////////////////////////////////
@@ -605,19 +1615,21 @@ pub mod instapump {
         //////////////////////////////////////////
         // START: Transfer left Token to admin
         //////////////////////////////////////////
-        transfer(
+        transfer_checked(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
-                TokenTransfer {
+                TransferChecked {
                     from: ctx.accounts.associated_bonding_curve.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
                     to: ctx.accounts.associated_user.to_account_info(),
                     authority: ctx.accounts.bonding_curve.to_account_info(),
                 },
                 signer,
             ),
             token_amount_admin_withdraw,
+            ctx.accounts.mint.decimals,
         )?;
-        msg!("Token transferred to admin");
+        debug_msg!("Token transferred to admin");
         //////////////////////////////////////////
         // END: Transfer left Token to admin
         //////////////////////////////////////////
@@ -625,16 +1637,12 @@ pub mod instapump {
         //////////////////////////////////////////
         // START: Transfer SOL share first buyer (sol_share_first_buyer_after_raydium)
         //////////////////////////////////////////
-        **ctx
-            .accounts
-            .bonding_curve
-            .to_account_info()
-            .try_borrow_mut_lamports()? -= global.sol_share_first_buyer_after_raydium;
-        **ctx
-            .accounts
-            .first_buyer_address
-            .to_account_info()
-            .try_borrow_mut_lamports()? += global.sol_share_first_buyer_after_raydium;
+        let bonding_curve_info = ctx.accounts.bonding_curve.to_account_info();
+        move_lamports(
+            &bonding_curve_info,
+            &ctx.accounts.first_buyer_address.to_account_info(),
+            first_buyer_sol_share,
+        )?;
         //////////////////////////////////////////
         // END: Transfer SOL share first buyer (sol_share_first_buyer_after_raydium)
         //////////////////////////////////////////
@@ -642,16 +1650,11 @@ pub mod instapump {
         //////////////////////////////////////////
         // START: Transfer SOL share instapump (sol_share_instapump_after_raydium)
         //////////////////////////////////////////
-        **ctx
-            .accounts
-            .bonding_curve
-            .to_account_info()
-            .try_borrow_mut_lamports()? -= global.sol_share_instapump_after_raydium;
-        **ctx
-            .accounts
-            .fee_recipient
-            .to_account_info()
-            .try_borrow_mut_lamports()? += global.sol_share_instapump_after_raydium;
+        move_lamports(
+            &bonding_curve_info,
+            &ctx.accounts.fee_recipient.to_account_info(),
+            instapump_sol_share,
+        )?;
         //////////////////////////////////////////
         // END: Transfer SOL share instapump (sol_share_instapump_after_raydium)
         //////////////////////////////////////////
@@ -659,16 +1662,11 @@ pub mod instapump {
         //////////////////////////////////////////
         // START: Transfer left SOL to admin
         //////////////////////////////////////////
-        **ctx
-            .accounts
-            .bonding_curve
-            .to_account_info()
-            .try_borrow_mut_lamports()? -= sol_amount;
-        **ctx
-            .accounts
-            .user
-            .to_account_info()
-            .try_borrow_mut_lamports()? += sol_amount;
+        move_lamports(
+            &bonding_curve_info,
+            &ctx.accounts.user.to_account_info(),
+            sol_amount_admin_withdraw,
+        )?;
         //////////////////////////////////////////
         // END: Transfer left SOL to admin
         //////////////////////////////////////////
@@ -677,103 +1675,997 @@ pub mod instapump {
         let bonding_curve = &mut ctx.accounts.bonding_curve;
         bonding_curve.real_sol_reserves = 0;
         bonding_curve.real_token_reserves = 0;
+        bonding_curve.direct_launch_escrow = 0;
+        // Curves migrated from before post records existed have none
+        if bonding_curve.post_record != Pubkey::default() {
+            ctx.accounts
+                .post_record
+                .as_mut()
+                .ok_or(ProgramError::InvalidPostRecord)?
+                .status = LaunchStatus::Migrated;
+        }
 
-        emit!(WithdrawEvent {
+        emit_cpi!(WithdrawEvent {
+            version: WITHDRAW_EVENT_VERSION,
             mint: mint_key,
+            bonding_curve: bonding_curve.key(),
             sol_amount: sol_amount_admin_withdraw,
             token_amount: token_amount_admin_withdraw,
+            creator: bonding_curve.creator_address,
+            creator_token_share,
+            first_buyer: bonding_curve.first_buyer_address,
+            first_buyer_sol_share,
+            instapump_sol_share,
+            direct_launch_sol,
             timestamp: Clock::get()?.unix_timestamp,
         });
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = user,
-        space = 8 + Global::LEN,
-        seeds = [b"global"],
-        bump
-    )]
-    pub global: Account<'info, Global>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referrer = &mut ctx.accounts.referrer;
+        referrer.authority = ctx.accounts.user.key();
+        referrer.tier = 0;
 
-#[derive(Accounts)]
-pub struct SetParams<'info> {
-    #[account(
-        mut, 
-        seeds = [b"global"], 
-        bump,
-        constraint = global.authority == user.key() @ ProgramError::NotAuthorized
-        )]
-    pub global: Account<'info, Global>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
+        emit_cpi!(RegisterReferrerEvent {
+            referrer: referrer.key(),
+            authority: referrer.authority,
+        });
 
-#[derive(Accounts)]
-#[instruction(name: String, symbol: String, uri: String, post_id: String, direct_launch: bool)]
-pub struct Create<'info> {
-    #[account(
-        init,
-        payer = user,
-        mint::decimals = 6,
-        mint::authority = mint_authority.key()
-    )]
-    pub mint: Account<'info, Mint>,
+        Ok(())
+    }
 
-    /// CHECK: This is safe as we're using it as a seed for PDA
-    #[account(seeds = [b"mint_authority", mint.key().as_ref()], bump)]
-    pub mint_authority: UncheckedAccount<'info>,
+    pub fn set_referrer_tier(ctx: Context<SetReferrerTier>, tier: u8) -> Result<()> {
+        require!(
+            (tier as usize) < REFERRAL_TIERS,
+            ProgramError::InvalidReferralTier
+        );
+        ctx.accounts.referrer.tier = tier;
 
-    #[account(
-        init,
-        payer = user,
-        space = 8,
-        seeds = [b"instagram_post", post_id.as_bytes()],
-        bump,
-    )]
-    pub instapump_post_account: UncheckedAccount<'info>,
+        emit_cpi!(SetReferrerTierEvent {
+            referrer: ctx.accounts.referrer.key(),
+            authority: ctx.accounts.referrer.authority,
+            tier,
+        });
 
-    #[account(seeds = [b"global"], bump)]
-    pub global: Box<Account<'info, Global>>,
+        Ok(())
+    }
 
-    #[account(mut, constraint = global.fee_recipient == fee_recipient.key())]
-    pub fee_recipient: UncheckedAccount<'info>,
+    pub fn claim_referral_fees(ctx: Context<ClaimReferralFees>) -> Result<()> {
+        // Everything above the rent-exempt minimum is accrued fees
+        let rent = Rent::get()?;
+        let minimum_balance = rent.minimum_balance(8 + Referrer::LEN);
+        let sol_amount = ctx
+            .accounts
+            .referrer
+            .to_account_info()
+            .lamports()
+            .saturating_sub(minimum_balance);
+        require!(sol_amount > 0, ProgramError::InsufficientFunds);
 
-    #[account(
-        init,
-        payer = user,
-        space = 8 + BondingCurve::LEN,
-        seeds = [b"bonding_curve", mint.key().as_ref()],
-        bump
-    )]
-    pub bonding_curve: Account<'info, BondingCurve>,
+        move_lamports(
+            &ctx.accounts.referrer.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            sol_amount,
+        )?;
 
-    #[account(
-        init_if_needed,
-        payer = user,
-        associated_token::mint = mint,
-        associated_token::authority = bonding_curve,
-    )]
-    pub associated_bonding_curve: Account<'info, TokenAccount>,
+        let referrer = &mut ctx.accounts.referrer;
+        referrer.total_claimed = referrer
+            .total_claimed
+            .checked_add(sol_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
 
-    #[account(
-        init_if_needed,
-        payer = user,
-        associated_token::mint = mint,
-        associated_token::authority = user,
-    )]
-    pub associated_user: Account<'info, TokenAccount>,
+        emit_cpi!(ReferralClaimEvent {
+            referrer: referrer.key(),
+            authority: referrer.authority,
+            sol_amount,
+            total_claimed: referrer.total_claimed,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
-    // #[account(
+        Ok(())
+    }
+
+    pub fn bid(ctx: Context<Bid>, sol_amount: u64) -> Result<()> {
+        let bonding_curve = &ctx.accounts.bonding_curve;
+        require!(
+            bonding_curve.phase == CurvePhase::Auction,
+            ProgramError::AuctionNotActive
+        );
+        let timestamp = Clock::get()?.unix_timestamp;
+        require!(
+            timestamp < bonding_curve.auction_end_time,
+            ProgramError::AuctionEnded
+        );
+        require!(sol_amount > 0, ProgramError::InsufficientFunds);
+
+        //////////////////////////////////////////
+        // START: Escrow bid SOL in the bonding-curve
+        //////////////////////////////////////////
+        transfer_sol(
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.bonding_curve.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            sol_amount,
+        )?;
+        //////////////////////////////////////////
+        // END: Escrow bid SOL in the bonding-curve
+        //////////////////////////////////////////
+
+        let auction_bid = &mut ctx.accounts.auction_bid;
+        auction_bid.bonding_curve = ctx.accounts.bonding_curve.key();
+        auction_bid.user = ctx.accounts.user.key();
+        auction_bid.sol_amount = auction_bid
+            .sol_amount
+            .checked_add(sol_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        let bonding_curve = &mut ctx.accounts.bonding_curve;
+        bonding_curve.auction_total_bids = bonding_curve
+            .auction_total_bids
+            .checked_add(sol_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        if bonding_curve.first_buyer_address == Pubkey::default() {
+            bonding_curve.first_buyer_address = ctx.accounts.user.key();
+        }
+
+        emit_cpi!(AuctionBidEvent {
+            mint: ctx.accounts.mint.key(),
+            bonding_curve: bonding_curve.key(),
+            user: ctx.accounts.user.key(),
+            sol_amount,
+            total_bids: bonding_curve.auction_total_bids,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn settle_auction(ctx: Context<SettleAuction>) -> Result<()> {
+        let global = &ctx.accounts.global;
+        let bonding_curve = &ctx.accounts.bonding_curve;
+        require!(
+            bonding_curve.phase == CurvePhase::Auction,
+            ProgramError::AuctionNotActive
+        );
+        let timestamp = Clock::get()?.unix_timestamp;
+        require!(
+            timestamp >= bonding_curve.auction_end_time,
+            ProgramError::AuctionNotEnded
+        );
+
+        //////////////////////////////////////////
+        // START: Compute the clearing price for the total demand
+        //
+        // Bids include trading fees, so the curve is priced on the demand net
+        // of fees. If that demand exceeds what is left before graduation, the
+        // fill is clamped and every bidder is filled pro-rata with a refund.
+        //////////////////////////////////////////
+        let total_bids = bonding_curve.auction_total_bids;
+        let net_demand = auction_net_demand(
+            total_bids,
+            global.fee_basis_points,
+            global.trading_fee_creator_percent_sol,
+        );
+        let demanded_tokens = calculate_tokens_for_sol(
+            net_demand,
+            bonding_curve.virtual_token_reserves,
+            bonding_curve.virtual_sol_reserves,
+        )?;
+        let tokens_to_graduation = bonding_curve.tokens_to_graduation()?;
+        let completes = demanded_tokens >= tokens_to_graduation;
+        let token_amount = demanded_tokens.min(tokens_to_graduation);
+
+        let (mut price_per_token, mut sol_cost, mut admin_fee, mut creator_fee) = (0, 0, 0, 0);
+        if token_amount > 0 {
+            let (price, cost, new_virtual_token_reserves, new_virtual_sol_reserves) =
+                calculate_price_and_sol(
+                    token_amount,
+                    bonding_curve.virtual_token_reserves,
+                    bonding_curve.virtual_sol_reserves,
+                    bonding_curve.real_token_reserves,
+                    bonding_curve.real_sol_reserves,
+                )?;
+            price_per_token = price;
+            sol_cost = cost;
+            admin_fee = (sol_cost * global.fee_basis_points as u64) / 10000;
+            creator_fee = (sol_cost * global.trading_fee_creator_percent_sol as u64) / 10000;
+
+            //////////////////////////////////////////
+            // START: Pay trading fees out of the escrow
+            //////////////////////////////////////////
+            let bonding_curve_info = ctx.accounts.bonding_curve.to_account_info();
+            move_lamports(
+                &bonding_curve_info,
+                &ctx.accounts.fee_recipient.to_account_info(),
+                admin_fee,
+            )?;
+            move_lamports(
+                &bonding_curve_info,
+                &ctx.accounts.creator.to_account_info(),
+                creator_fee,
+            )?;
+            //////////////////////////////////////////
+            // END: Pay trading fees out of the escrow
+            //////////////////////////////////////////
+
+            ctx.accounts
+                .bonding_curve
+                .update_price_accumulator(timestamp)?;
+            ctx.accounts.bonding_curve.apply_buy(
+                token_amount,
+                sol_cost,
+                new_virtual_token_reserves,
+                new_virtual_sol_reserves,
+            )?;
+            let trade_sequence = ctx.accounts.bonding_curve.record_trade(
+                true,
+                sol_cost,
+                token_amount,
+                admin_fee + creator_fee,
+            )?;
+            ctx.accounts.stats.record_trade(sol_cost, admin_fee)?;
+
+            // The fill is one trade for the whole auction; the bonding-curve
+            // holds the tokens until each bidder claims their share
+            let bonding_curve = &ctx.accounts.bonding_curve;
+            emit_cpi!(TradeEvent {
+                version: TRADE_EVENT_VERSION,
+                mint: ctx.accounts.mint.key(),
+                bonding_curve: bonding_curve.key(),
+                creator: ctx.accounts.creator.key(),
+                sol_amount: sol_cost,
+                token_amount,
+                tokens_received: token_amount,
+                price_per_token,
+                is_buy: true,
+                is_creator_buy: false,
+                user: bonding_curve.key(),
+                recipient: bonding_curve.key(),
+                admin_fee,
+                creator_fee,
+                launch_window_fee: 0,
+                referrer: Pubkey::default(),
+                referrer_fee: 0,
+                trade_sequence,
+                timestamp,
+                virtual_sol_reserves: bonding_curve.virtual_sol_reserves,
+                virtual_token_reserves: bonding_curve.virtual_token_reserves,
+                real_sol_reserves: bonding_curve.real_sol_reserves,
+                real_token_reserves: bonding_curve.real_token_reserves,
+            });
+        }
+        let sol_filled = sol_cost + admin_fee + creator_fee;
+        //////////////////////////////////////////
+        // END: Compute the clearing price for the total demand
+        //////////////////////////////////////////
+
+        let bonding_curve = &mut ctx.accounts.bonding_curve;
+        bonding_curve.phase = CurvePhase::Trading;
+        bonding_curve.auction_tokens_sold = token_amount;
+        bonding_curve.auction_sol_filled = sol_filled;
+        bonding_curve.auction_unclaimed_tokens = token_amount;
+        bonding_curve.auction_unclaimed_refunds = total_bids.saturating_sub(sol_filled);
+
+        if token_amount > 0 && (completes || bonding_curve.graduation_reached()) {
+            bonding_curve.complete = true;
+            ctx.accounts.stats.record_graduation()?;
+            emit_cpi!(CompleteEvent {
+                mint: ctx.accounts.mint.key(),
+                bonding_curve: bonding_curve.key(),
+                timestamp,
+                virtual_sol_reserves: bonding_curve.virtual_sol_reserves,
+                virtual_token_reserves: bonding_curve.virtual_token_reserves,
+                real_sol_reserves: bonding_curve.real_sol_reserves,
+                real_token_reserves: bonding_curve.real_token_reserves,
+            });
+        }
+
+        emit_cpi!(AuctionSettledEvent {
+            mint: ctx.accounts.mint.key(),
+            bonding_curve: bonding_curve.key(),
+            total_bids,
+            token_amount,
+            sol_filled,
+            price_per_token,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn claim_auction(ctx: Context<ClaimAuction>) -> Result<()> {
+        let bonding_curve = &ctx.accounts.bonding_curve;
+        require!(
+            bonding_curve.phase == CurvePhase::Trading,
+            ProgramError::AuctionNotSettled
+        );
+
+        let (token_amount, sol_spent, sol_refund) = auction_claim(
+            bonding_curve.auction_tokens_sold,
+            bonding_curve.auction_sol_filled,
+            ctx.accounts.auction_bid.sol_amount,
+            bonding_curve.auction_total_bids,
+        );
+        let token_amount = token_amount.min(bonding_curve.auction_unclaimed_tokens);
+        let sol_refund = sol_refund.min(bonding_curve.auction_unclaimed_refunds);
+
+        //////////////////////////////////////////
+        // START: Transfer Tokens from bonding-curve to user
+        //////////////////////////////////////////
+        if token_amount > 0 {
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.associated_bonding_curve.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.associated_user.to_account_info(),
+                        authority: ctx.accounts.bonding_curve.to_account_info(),
+                    },
+                    &[&[
+                        b"bonding_curve",
+                        ctx.accounts.mint.key().as_ref(),
+                        &[ctx.bumps.bonding_curve],
+                    ]],
+                ),
+                token_amount,
+                ctx.accounts.mint.decimals,
+            )?;
+        }
+        //////////////////////////////////////////
+        // END: Transfer Tokens from bonding-curve to user
+        //////////////////////////////////////////
+
+        //////////////////////////////////////////
+        // START: Refund unfilled SOL from bonding-curve to user
+        //////////////////////////////////////////
+        move_lamports(
+            &ctx.accounts.bonding_curve.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            sol_refund,
+        )?;
+        //////////////////////////////////////////
+        // END: Refund unfilled SOL from bonding-curve to user
+        //////////////////////////////////////////
+
+        // The clearing cost already includes the trading fees
+        let user_position = &mut ctx.accounts.user_position;
+        let is_new_trader = user_position.first_trade_at == 0;
+        user_position.bonding_curve = ctx.accounts.bonding_curve.key();
+        user_position.user = ctx.accounts.user.key();
+        user_position.record_buy(token_amount, sol_spent, 0, Clock::get()?.unix_timestamp)?;
+
+        let bonding_curve = &mut ctx.accounts.bonding_curve;
+        bonding_curve.auction_unclaimed_tokens -= token_amount;
+        bonding_curve.auction_unclaimed_refunds -= sol_refund;
+        if is_new_trader {
+            bonding_curve.record_new_trader()?;
+        }
+
+        emit_cpi!(AuctionClaimEvent {
+            mint: ctx.accounts.mint.key(),
+            bonding_curve: bonding_curve.key(),
+            user: ctx.accounts.user.key(),
+            token_amount,
+            sol_spent,
+            sol_refund,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn init_price_history(
+        ctx: Context<InitPriceHistory>,
+        minute_capacity: u32,
+        hour_capacity: u32,
+    ) -> Result<()> {
+        require!(
+            minute_capacity > 0 && hour_capacity > 0,
+            ProgramError::InvalidPriceHistoryCapacity
+        );
+        PriceHistory::check_capacity(minute_capacity, hour_capacity)?;
+        let price_history = &mut ctx.accounts.price_history;
+        price_history.bonding_curve = ctx.accounts.bonding_curve.key();
+        price_history.minute.capacity = minute_capacity;
+        price_history.hour.capacity = hour_capacity;
+        ctx.accounts.bonding_curve.price_history_initialized = true;
+        Ok(())
+    }
+
+    /// Grows the candle buffers, up to MAX_PRICE_HISTORY_CANDLES in total.
+    pub fn resize_price_history(
+        ctx: Context<ResizePriceHistory>,
+        minute_capacity: u32,
+        hour_capacity: u32,
+    ) -> Result<()> {
+        PriceHistory::check_capacity(minute_capacity, hour_capacity)?;
+        let price_history = &mut ctx.accounts.price_history;
+        price_history.minute.grow(minute_capacity)?;
+        price_history.hour.grow(hour_capacity)?;
+        Ok(())
+    }
+
+    /// Time-weighted average price over at least the last `window_seconds`,
+    /// in the units of `price_per_token`. Returned through return data.
+    pub fn get_twap(ctx: Context<GetTwap>, window_seconds: u32) -> Result<u64> {
+        ctx.accounts
+            .bonding_curve
+            .twap(window_seconds, Clock::get()?.unix_timestamp)
+    }
+
+    /// Sweeps Token-2022 transfer fees withheld in the token accounts passed
+    /// as remaining accounts into the mint, then pays everything withheld on
+    /// the mint to the creator. Anyone can crank it.
+    pub fn harvest_transfer_fees<'info>(
+        ctx: Context<'_, '_, '_, 'info, HarvestTransferFees<'info>>,
+    ) -> Result<()> {
+        if !ctx.remaining_accounts.is_empty() {
+            harvest_withheld_tokens_to_mint(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    HarvestWithheldTokensToMint {
+                        token_program_id: ctx.accounts.token_program.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                    },
+                ),
+                ctx.remaining_accounts.to_vec(),
+            )?;
+        }
+
+        let balance_before = ctx.accounts.creator_token_account.amount;
+        withdraw_withheld_tokens_from_mint(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            WithdrawWithheldTokensFromMint {
+                token_program_id: ctx.accounts.token_program.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                destination: ctx.accounts.creator_token_account.to_account_info(),
+                authority: ctx.accounts.mint_authority.to_account_info(),
+            },
+            &[&[
+                b"mint_authority",
+                ctx.accounts.mint.key().as_ref(),
+                &[ctx.bumps.mint_authority],
+            ]],
+        ))?;
+        ctx.accounts.creator_token_account.reload()?;
+
+        emit_cpi!(HarvestTransferFeesEvent {
+            mint: ctx.accounts.mint.key(),
+            creator: ctx.accounts.creator.key(),
+            token_amount: ctx.accounts.creator_token_account.amount - balance_before,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Lets the creator fix the token metadata, within the update window and
+    /// trade limit set in Global and until the metadata is locked.
+    pub fn update_metadata(
+        ctx: Context<UpdateMetadata>,
+        name: String,
+        symbol: String,
+        uri: String,
+        metadata_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        validate_metadata(&name, &symbol, &uri)?;
+        let clock = Clock::get()?;
+        require!(
+            !ctx.accounts.bonding_curve.metadata_locked,
+            ProgramError::MetadataLocked
+        );
+        require!(
+            ctx.accounts
+                .bonding_curve
+                .metadata_update_open(&ctx.accounts.global, clock.unix_timestamp),
+            ProgramError::MetadataUpdateWindowClosed
+        );
+
+        let mint_key = ctx.accounts.mint.key();
+        let seeds = &[
+            b"mint_authority",
+            mint_key.as_ref(),
+            &[ctx.bumps.mint_authority],
+        ];
+        let signer = &[&seeds[..]];
+
+        if ctx.accounts.token_program.key() == Token2022::id() {
+            for (field, value) in [
+                (Field::Name, &name),
+                (Field::Symbol, &symbol),
+                (Field::Uri, &uri),
+            ] {
+                token_metadata_update_field(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        TokenMetadataUpdateField {
+                            token_program_id: ctx.accounts.token_program.to_account_info(),
+                            metadata: ctx.accounts.mint.to_account_info(),
+                            update_authority: ctx.accounts.mint_authority.to_account_info(),
+                        },
+                        signer,
+                    ),
+                    field,
+                    value.clone(),
+                )?;
+            }
+
+            // Longer values grow the mint, which has to stay rent-exempt
+            let mint_info = ctx.accounts.mint.to_account_info();
+            let shortfall = Rent::get()?
+                .minimum_balance(mint_info.data_len())
+                .saturating_sub(mint_info.lamports());
+            if shortfall > 0 {
+                transfer_sol(
+                    ctx.accounts.creator.to_account_info(),
+                    mint_info,
+                    ctx.accounts.system_program.to_account_info(),
+                    shortfall,
+                )?;
+            }
+        } else {
+            let token_metadata_program = ctx
+                .accounts
+                .token_metadata_program
+                .as_ref()
+                .ok_or(ProgramError::MetadataAccountsRequired)?;
+            let metadata = ctx
+                .accounts
+                .metadata
+                .as_ref()
+                .ok_or(ProgramError::MetadataAccountsRequired)?;
+            // Keep the verified creator and collection as they are
+            let current = MetadataAccount::try_deserialize(&mut &metadata.try_borrow_data()?[..])?;
+            update_metadata_accounts_v2(
+                CpiContext::new_with_signer(
+                    token_metadata_program.to_account_info(),
+                    UpdateMetadataAccountsV2 {
+                        metadata: metadata.to_account_info(),
+                        update_authority: ctx.accounts.mint_authority.to_account_info(),
+                    },
+                    signer,
+                ),
+                None,
+                Some(DataV2 {
+                    name: name.clone(),
+                    symbol: symbol.clone(),
+                    uri: uri.clone(),
+                    seller_fee_basis_points: 0,
+                    creators: current.creators.clone(),
+                    collection: current.collection.clone(),
+                    uses: None,
+                }),
+                None,
+                None,
+            )?;
+        }
+
+        ctx.accounts.bonding_curve.metadata_hash = metadata_hash.unwrap_or_default();
+
+        emit_cpi!(MetadataUpdateEvent {
+            mint: mint_key,
+            name,
+            symbol,
+            uri,
+            metadata_hash,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Permanently revokes the creator's ability to update the metadata.
+    pub fn lock_metadata(ctx: Context<UpdateMetadata>) -> Result<()> {
+        require!(
+            !ctx.accounts.bonding_curve.metadata_locked,
+            ProgramError::MetadataLocked
+        );
+
+        let mint_key = ctx.accounts.mint.key();
+        let seeds = &[
+            b"mint_authority",
+            mint_key.as_ref(),
+            &[ctx.bumps.mint_authority],
+        ];
+        let signer = &[&seeds[..]];
+
+        if ctx.accounts.token_program.key() == Token2022::id() {
+            token_metadata_update_authority(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TokenMetadataUpdateAuthority {
+                        token_program_id: ctx.accounts.token_program.to_account_info(),
+                        metadata: ctx.accounts.mint.to_account_info(),
+                        current_authority: ctx.accounts.mint_authority.to_account_info(),
+                        new_authority: ctx.accounts.mint_authority.to_account_info(),
+                    },
+                    signer,
+                ),
+                OptionalNonZeroPubkey::default(),
+            )?;
+        } else {
+            let token_metadata_program = ctx
+                .accounts
+                .token_metadata_program
+                .as_ref()
+                .ok_or(ProgramError::MetadataAccountsRequired)?;
+            let metadata = ctx
+                .accounts
+                .metadata
+                .as_ref()
+                .ok_or(ProgramError::MetadataAccountsRequired)?;
+            update_metadata_accounts_v2(
+                CpiContext::new_with_signer(
+                    token_metadata_program.to_account_info(),
+                    UpdateMetadataAccountsV2 {
+                        metadata: metadata.to_account_info(),
+                        update_authority: ctx.accounts.mint_authority.to_account_info(),
+                    },
+                    signer,
+                ),
+                None,
+                None,
+                None,
+                Some(false), // is_mutable
+            )?;
+        }
+
+        ctx.accounts.bonding_curve.metadata_locked = true;
+
+        emit_cpi!(MetadataLockedEvent {
+            mint: mint_key,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Creates the collection NFT that every launch is verified into. It is
+    /// owned by the collection authority PDA and can only be created once.
+    /// Token-2022 launches keep their metadata on the mint, which Metaplex
+    /// can't verify, so they stay outside the collection.
+    pub fn init_collection(
+        ctx: Context<InitCollection>,
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        validate_metadata(&name, &symbol, &uri)?;
+
+        let seeds = &[
+            "collection_authority".as_bytes(),
+            &[ctx.bumps.collection_authority],
+        ];
+        let signer = [&seeds[..]];
+
+        mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.collection_mint.to_account_info(),
+                    to: ctx.accounts.collection_token_account.to_account_info(),
+                    authority: ctx.accounts.collection_authority.to_account_info(),
+                },
+                &signer,
+            ),
+            1,
+        )?;
+
+        create_metadata_accounts_v3(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                CreateMetadataAccountsV3 {
+                    metadata: ctx.accounts.collection_metadata.to_account_info(),
+                    mint: ctx.accounts.collection_mint.to_account_info(),
+                    mint_authority: ctx.accounts.collection_authority.to_account_info(),
+                    payer: ctx.accounts.user.to_account_info(),
+                    update_authority: ctx.accounts.collection_authority.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    rent: ctx.accounts.rent.to_account_info(),
+                },
+                &signer,
+            ),
+            DataV2 {
+                name,
+                symbol,
+                uri,
+                seller_fee_basis_points: 0,
+                creators: None,
+                collection: None,
+                uses: None,
+            },
+            true, // is_mutable
+            true, // update_authority_is_signer
+            Some(CollectionDetails::V1 { size: 0 }),
+        )?;
+
+        create_master_edition_v3(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                CreateMasterEditionV3 {
+                    edition: ctx.accounts.collection_master_edition.to_account_info(),
+                    mint: ctx.accounts.collection_mint.to_account_info(),
+                    update_authority: ctx.accounts.collection_authority.to_account_info(),
+                    mint_authority: ctx.accounts.collection_authority.to_account_info(),
+                    payer: ctx.accounts.user.to_account_info(),
+                    metadata: ctx.accounts.collection_metadata.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    rent: ctx.accounts.rent.to_account_info(),
+                },
+                &signer,
+            ),
+            Some(0), // max_supply
+        )?;
+
+        let collection_mint = ctx.accounts.collection_mint.key();
+        ctx.accounts.global.collection_mint = collection_mint;
+
+        emit_cpi!(InitCollectionEvent {
+            collection_mint,
+            collection_authority: ctx.accounts.collection_authority.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Grows a Global account written by an older version of the program to
+    /// the current layout, and creates the Stats account if it is missing.
+    /// New fields start at their defaults, with Instagram enabled as the only
+    /// platform, as before.
+    pub fn migrate_global(ctx: Context<MigrateGlobal>) -> Result<()> {
+        let global_info = ctx.accounts.global.to_account_info();
+        grow_account(
+            &global_info,
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            8 + Global::LEN,
+        )?;
+
+        let mut global = Global::try_deserialize(&mut &global_info.try_borrow_data()?[..])?;
+        require!(
+            global.authority == ctx.accounts.user.key(),
+            ProgramError::NotAuthorized
+        );
+        require!(
+            global.version < GLOBAL_VERSION,
+            ProgramError::AlreadyMigrated
+        );
+        if global.version == 0 {
+            global.platforms[SocialPlatform::Instagram as usize].enabled = true;
+        }
+        let from_version = global.version;
+        global.version = GLOBAL_VERSION;
+        global.try_serialize(&mut &mut global_info.try_borrow_mut_data()?[..])?;
+
+        emit_cpi!(MigrateGlobalEvent {
+            from_version,
+            version: GLOBAL_VERSION,
+        });
+
+        Ok(())
+    }
+
+    /// Grows a bonding curve written by an older version of the program to the
+    /// current layout, and counts it in Stats. Legacy curves keep no post
+    /// record; create checks their posts' `instagram_post` markers instead.
+    pub fn migrate_bonding_curve(ctx: Context<MigrateBondingCurve>) -> Result<()> {
+        let bonding_curve_info = ctx.accounts.bonding_curve.to_account_info();
+        grow_account(
+            &bonding_curve_info,
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            8 + BondingCurve::LEN,
+        )?;
+
+        let mut bonding_curve =
+            BondingCurve::try_deserialize(&mut &bonding_curve_info.try_borrow_data()?[..])?;
+        require!(
+            bonding_curve.version < BONDING_CURVE_VERSION,
+            ProgramError::AlreadyMigrated
+        );
+        let from_version = bonding_curve.version;
+        if from_version == 0 {
+            bonding_curve.start_price_accumulator(Clock::get()?.unix_timestamp);
+            bonding_curve.platform = SocialPlatform::Instagram;
+
+            let stats = &mut ctx.accounts.stats;
+            stats.record_launch(0)?;
+            if bonding_curve.complete {
+                stats.record_graduation()?;
+            }
+        }
+        bonding_curve.version = BONDING_CURVE_VERSION;
+        bonding_curve.try_serialize(&mut &mut bonding_curve_info.try_borrow_mut_data()?[..])?;
+
+        emit_cpi!(MigrateBondingCurveEvent {
+            mint: bonding_curve.mint,
+            bonding_curve: bonding_curve_info.key(),
+            from_version,
+            version: BONDING_CURVE_VERSION,
+        });
+
+        Ok(())
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct MigrateGlobal<'info> {
+    /// CHECK: Deserialized in the instruction once grown to the current layout
+    #[account(mut, seeds = [b"global"], bump, owner = crate::ID)]
+    pub global: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + Stats::LEN,
+        seeds = [b"stats"],
+        bump
+    )]
+    pub stats: Account<'info, Stats>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct MigrateBondingCurve<'info> {
+    #[account(
+        seeds = [b"global"],
+        bump,
+        constraint = global.authority == user.key() @ ProgramError::NotAuthorized
+    )]
+    pub global: Box<Account<'info, Global>>,
+    /// CHECK: Only used as the bonding curve seed
+    pub mint: UncheckedAccount<'info>,
+    /// CHECK: Deserialized in the instruction once grown to the current layout
+    #[account(
+        mut,
+        seeds = [b"bonding_curve", mint.key().as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub bonding_curve: UncheckedAccount<'info>,
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: Account<'info, Stats>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Global::LEN,
+        seeds = [b"global"],
+        bump
+    )]
+    pub global: Account<'info, Global>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitStats<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Stats::LEN,
+        seeds = [b"stats"],
+        bump
+    )]
+    pub stats: Account<'info, Stats>,
+    #[account(
+        seeds = [b"global"],
+        bump,
+        constraint = global.authority == user.key() @ ProgramError::NotAuthorized
+    )]
+    pub global: Account<'info, Global>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetParams<'info> {
+    #[account(
+        mut, 
+        seeds = [b"global"], 
+        bump,
+        constraint = global.authority == user.key() @ ProgramError::NotAuthorized
+        )]
+    pub global: Account<'info, Global>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(
+    name: String,
+    symbol: String,
+    uri: String,
+    platform: SocialPlatform,
+    post_id: String,
+    args: CreateArgs
+)]
+pub struct Create<'info> {
+    /// Created in the instruction, under whichever token program is passed
+    #[account(mut)]
+    pub mint: Signer<'info>,
+
+    /// CHECK: This is safe as we're using it as a seed for PDA
+    #[account(seeds = [b"mint_authority", mint.key().as_ref()], bump)]
+    pub mint_authority: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + PostRecord::LEN,
+        seeds = [b"social_post", [platform as u8].as_ref(), args.post_id_hash.as_ref()],
+        bump,
+    )]
+    pub post_record: Box<Account<'info, PostRecord>>,
+
+    #[account(seeds = [b"global"], bump)]
+    pub global: Box<Account<'info, Global>>,
+
+    #[account(mut, constraint = global.fee_recipient == fee_recipient.key())]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: Box<Account<'info, Stats>>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + BondingCurve::LEN,
+        seeds = [b"bonding_curve", mint.key().as_ref()],
+        bump
+    )]
+    pub bonding_curve: Account<'info, BondingCurve>,
+
+    #[account(
+        mut,
+        address = get_associated_token_address_with_program_id(
+            &bonding_curve.key(),
+            &mint.key(),
+            &token_program.key()
+        )
+    )]
+    /// CHECK: Created in the instruction once the mint exists
+    pub associated_bonding_curve: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        address = get_associated_token_address_with_program_id(
+            &user.key(),
+            &mint.key(),
+            &token_program.key()
+        )
+    )]
+    /// CHECK: Created in the instruction once the mint exists
+    pub associated_user: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserPosition::LEN,
+        seeds = [b"user_position", bonding_curve.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub creator_position: Box<Account<'info, UserPosition>>,
+
+    // #[account(
     //     mut
     //     // init_if_needed,
     //     // payer = user,
@@ -785,170 +2677,1288 @@ pub struct Create<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
-    #[account(seeds = [b"event_authority"], bump)]
-    /// CHECK: This account is only used as a PDA for event emission
-    pub event_authority: UncheckedAccount<'info>,
-
-    pub token_metadata_program: Program<'info, Metaplex>,
+    /// Only used for legacy SPL Token mints
+    pub token_metadata_program: Option<Program<'info, Metaplex>>,
     #[account(mut)]
     /// CHECK: This account is checked in the instruction
-    pub metadata: UncheckedAccount<'info>,
+    pub metadata: Option<UncheckedAccount<'info>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+    /// CHECK: Instructions sysvar, read for the post ownership attestation
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    // Collection accounts, only needed for legacy SPL Token launches once
+    // init_collection has run
+    /// CHECK: PDA that signs the collection verification
+    #[account(seeds = [b"collection_authority"], bump)]
+    pub collection_authority: Option<UncheckedAccount<'info>>,
+    /// CHECK: Checked against Global
+    #[account(address = global.collection_mint)]
+    pub collection_mint: Option<UncheckedAccount<'info>>,
+    #[account(mut)]
+    /// CHECK: Checked by the Metaplex program
+    pub collection_metadata: Option<UncheckedAccount<'info>>,
+    /// CHECK: Checked by the Metaplex program
+    pub collection_master_edition: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: `instagram_post` marker of the previous program version, only
+    /// needed for Instagram posts and checked in the instruction
+    pub legacy_post_account: Option<UncheckedAccount<'info>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Buy<'info> {
+    #[account(seeds = [b"global"], bump)]
+    pub global: Account<'info, Global>,
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: Account<'info, Stats>,
+    #[account(mut, constraint = global.fee_recipient == fee_recipient.key())]
+    pub fee_recipient: UncheckedAccount<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"bonding_curve", mint.key().as_ref()],
+        bump,
+    )]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = bonding_curve,
+        associated_token::token_program = token_program,
+    )]
+    pub associated_bonding_curve: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = bonding_curve.creator_address == creator.key())]
+    pub creator: UncheckedAccount<'info>,
+    /// CHECK: Wallet the tokens are delivered to; defaults to `user`
+    pub recipient: Option<UncheckedAccount<'info>>,
+    #[account(mut)]
+    /// CHECK: Checked against the recipient's associated token address in the
+    /// instruction and created there if it doesn't exist yet
+    pub associated_user: UncheckedAccount<'info>,
+    /// Position of the wallet that holds the bought tokens, the recipient
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::LEN,
+        seeds = [
+            b"user_position",
+            bonding_curve.key().as_ref(),
+            recipient.as_ref().map_or(user.key(), |recipient| recipient.key()).as_ref()
+        ],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(mut)]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        mut,
+        seeds = [b"price_history", bonding_curve.key().as_ref()],
+        bump
+    )]
+    pub price_history: Option<Account<'info, PriceHistory>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Sell<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(seeds = [b"global"], bump)]
+    pub global: Account<'info, Global>,
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: Account<'info, Stats>,
+    #[account(
+        mut,
+        seeds = [b"bonding_curve", mint.key().as_ref()],
+        bump
+    )]
+    pub bonding_curve: Account<'info, BondingCurve>,
+
+    #[account(mut, constraint = global.fee_recipient == fee_recipient.key())]
+    pub fee_recipient: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = bonding_curve,
+        associated_token::token_program = token_program,
+    )]
+    pub associated_bonding_curve: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, constraint = bonding_curve.creator_address == creator.key())]
+    pub creator: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub associated_user: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::LEN,
+        seeds = [b"user_position", bonding_curve.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+    #[account(mut)]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        mut,
+        seeds = [b"price_history", bonding_curve.key().as_ref()],
+        bump
+    )]
+    pub price_history: Option<Account<'info, PriceHistory>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"bonding_curve", mint.key().as_ref()],
+        bump
+    )]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    pub global: Account<'info, Global>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = bonding_curve,
+        associated_token::token_program = token_program,
+    )]
+    pub associated_bonding_curve: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub associated_user: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = global.fee_recipient == fee_recipient.key())]
+    pub fee_recipient: UncheckedAccount<'info>,
+    #[account(mut, constraint = bonding_curve.first_buyer_address == first_buyer_address.key())]
+    pub first_buyer_address: UncheckedAccount<'info>,
+    #[account(mut, constraint = bonding_curve.creator_address == creator.key())]
+    pub creator: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = creator,
+        associated_token::token_program = token_program,
+    )]
+    pub creator_associated_user: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = bonding_curve.post_record)]
+    pub post_record: Option<Account<'info, PostRecord>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct HarvestTransferFees<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Withdraw-withheld authority of the mint
+    #[account(seeds = [b"mint_authority", mint.key().as_ref()], bump)]
+    pub mint_authority: UncheckedAccount<'info>,
+    #[account(seeds = [b"bonding_curve", mint.key().as_ref()], bump)]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    #[account(constraint = bonding_curve.creator_address == creator.key())]
+    pub creator: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = mint,
+        associated_token::authority = creator,
+        associated_token::token_program = token_program,
+    )]
+    pub creator_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct InitCollection<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump,
+        constraint = global.authority == user.key() @ ProgramError::NotAuthorized
+    )]
+    pub global: Box<Account<'info, Global>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    /// CHECK: PDA that owns the collection NFT and signs for it
+    #[account(seeds = [b"collection_authority"], bump)]
+    pub collection_authority: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"collection_mint"],
+        bump,
+        mint::decimals = 0,
+        mint::authority = collection_authority,
+        mint::freeze_authority = collection_authority,
+        mint::token_program = token_program,
+    )]
+    pub collection_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = collection_mint,
+        associated_token::authority = collection_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub collection_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    /// CHECK: Created by the Metaplex program
+    pub collection_metadata: UncheckedAccount<'info>,
+    #[account(mut)]
+    /// CHECK: Created by the Metaplex program
+    pub collection_master_edition: UncheckedAccount<'info>,
+    pub token_metadata_program: Program<'info, Metaplex>,
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub rent: Sysvar<'info, Rent>,
 }
 
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateMetadata<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(seeds = [b"global"], bump)]
+    pub global: Account<'info, Global>,
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Update authority of the metadata
+    #[account(seeds = [b"mint_authority", mint.key().as_ref()], bump)]
+    pub mint_authority: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"bonding_curve", mint.key().as_ref()],
+        bump,
+        constraint = bonding_curve.creator_address == creator.key() @ ProgramError::NotAuthorized
+    )]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    /// Only used for legacy SPL Token mints
+    pub token_metadata_program: Option<Program<'info, Metaplex>>,
+    #[account(mut)]
+    /// CHECK: Checked by the Metaplex program against the update authority
+    pub metadata: Option<UncheckedAccount<'info>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct GetTwap<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds = [b"bonding_curve", mint.key().as_ref()], bump)]
+    pub bonding_curve: Account<'info, BondingCurve>,
+}
+
+#[derive(Accounts)]
+#[instruction(minute_capacity: u32, hour_capacity: u32)]
+pub struct InitPriceHistory<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds = [b"bonding_curve", mint.key().as_ref()], bump)]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + PriceHistory::space(minute_capacity, hour_capacity),
+        seeds = [b"price_history", bonding_curve.key().as_ref()],
+        bump
+    )]
+    pub price_history: Account<'info, PriceHistory>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(minute_capacity: u32, hour_capacity: u32)]
+pub struct ResizePriceHistory<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds = [b"bonding_curve", mint.key().as_ref()], bump)]
+    pub bonding_curve: Account<'info, BondingCurve>,
+    #[account(
+        mut,
+        seeds = [b"price_history", bonding_curve.key().as_ref()],
+        bump,
+        realloc = 8 + PriceHistory::space(minute_capacity, hour_capacity),
+        realloc::payer = payer,
+        realloc::zero = false
+    )]
+    pub price_history: Account<'info, PriceHistory>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Referrer::LEN,
+        seeds = [b"referrer", user.key().as_ref()],
+        bump
+    )]
+    pub referrer: Account<'info, Referrer>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetReferrerTier<'info> {
+    #[account(
+        seeds = [b"global"],
+        bump,
+        constraint = global.authority == user.key() @ ProgramError::NotAuthorized
+    )]
+    pub global: Account<'info, Global>,
+    #[account(mut)]
+    pub referrer: Account<'info, Referrer>,
+    pub user: Signer<'info>,
+}
+
+#[event_cpi]
 #[derive(Accounts)]
-pub struct Buy<'info> {
-    #[account(seeds = [b"global"], bump)]
-    pub global: Account<'info, Global>,
-    #[account(mut, constraint = global.fee_recipient == fee_recipient.key())]
-    pub fee_recipient: UncheckedAccount<'info>,
-    pub mint: Account<'info, Mint>,
+pub struct ClaimReferralFees<'info> {
     #[account(
         mut,
-        seeds = [b"bonding_curve", mint.key().as_ref()],
+        seeds = [b"referrer", user.key().as_ref()],
         bump,
+        constraint = referrer.authority == user.key() @ ProgramError::NotAuthorized
     )]
-    pub bonding_curve: Account<'info, BondingCurve>,
+    pub referrer: Account<'info, Referrer>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Bid<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
-        associated_token::mint = mint,
-        associated_token::authority = bonding_curve,
+        seeds = [b"bonding_curve", mint.key().as_ref()],
+        bump
     )]
-    pub associated_bonding_curve: Account<'info, TokenAccount>,
-    #[account(mut, constraint = bonding_curve.creator_address == creator.key())]
-    pub creator: UncheckedAccount<'info>,
+    pub bonding_curve: Account<'info, BondingCurve>,
     #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = user,
+        init_if_needed,
+        payer = user,
+        space = 8 + AuctionBid::LEN,
+        seeds = [b"auction_bid", bonding_curve.key().as_ref(), user.key().as_ref()],
+        bump
     )]
-    pub associated_user: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
+    pub auction_bid: Account<'info, AuctionBid>,
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-pub struct Sell<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+pub struct SettleAuction<'info> {
     #[account(seeds = [b"global"], bump)]
     pub global: Account<'info, Global>,
+    #[account(mut, seeds = [b"stats"], bump)]
+    pub stats: Account<'info, Stats>,
+    pub mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
         seeds = [b"bonding_curve", mint.key().as_ref()],
         bump
     )]
     pub bonding_curve: Account<'info, BondingCurve>,
-
     #[account(mut, constraint = global.fee_recipient == fee_recipient.key())]
     pub fee_recipient: UncheckedAccount<'info>,
-
-    pub mint: Account<'info, Mint>,
-
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = bonding_curve,
-    )]
-    pub associated_bonding_curve: Account<'info, TokenAccount>,
-
     #[account(mut, constraint = bonding_curve.creator_address == creator.key())]
     pub creator: UncheckedAccount<'info>,
-
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = user,
-    )]
-    pub associated_user: Account<'info, TokenAccount>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
+pub struct ClaimAuction<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
         seeds = [b"bonding_curve", mint.key().as_ref()],
         bump
     )]
     pub bonding_curve: Account<'info, BondingCurve>,
-    pub global: Account<'info, Global>,
-    pub mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"auction_bid", bonding_curve.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub auction_bid: Account<'info, AuctionBid>,
     #[account(
         mut,
         associated_token::mint = mint,
         associated_token::authority = bonding_curve,
+        associated_token::token_program = token_program,
     )]
-    pub associated_bonding_curve: Account<'info, TokenAccount>,
+    pub associated_bonding_curve: InterfaceAccount<'info, TokenAccount>,
     #[account(
-        mut,
+        init_if_needed,
+        payer = user,
         associated_token::mint = mint,
         associated_token::authority = user,
+        associated_token::token_program = token_program,
     )]
-    pub associated_user: Account<'info, TokenAccount>,
-    #[account(mut, constraint = global.fee_recipient == fee_recipient.key())]
-    pub fee_recipient: UncheckedAccount<'info>,
-    #[account(mut, constraint = bonding_curve.first_buyer_address == first_buyer_address.key())]
-    pub first_buyer_address: UncheckedAccount<'info>,
-    #[account(mut, constraint = bonding_curve.creator_address == creator.key())]
-    pub creator: UncheckedAccount<'info>,
+    pub associated_user: InterfaceAccount<'info, TokenAccount>,
     #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = creator,
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::LEN,
+        seeds = [b"user_position", bonding_curve.key().as_ref(), user.key().as_ref()],
+        bump
     )]
-    pub creator_associated_user: Account<'info, TokenAccount>,
+    pub user_position: Account<'info, UserPosition>,
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+pub const TOKEN_DECIMALS: u8 = 6;
+pub const MAX_NAME_LEN: usize = 32;
+pub const MAX_SYMBOL_LEN: usize = 10;
+pub const MAX_URI_LEN: usize = 200;
+pub const ALLOWED_URI_SCHEMES: [&str; 3] = ["https://", "ipfs://", "ar://"];
+pub const MAX_ATTESTERS: usize = 4;
+pub const GLOBAL_VERSION: u8 = 1; // layout version, see migrate_global
+pub const BONDING_CURVE_VERSION: u8 = 1; // layout version, see migrate_bonding_curve
+pub const ATTESTATION_DOMAIN: &[u8] = b"instapump:post-attestation";
+
+#[account]
+#[derive(Default)]
+pub struct Global {
+    pub initialized: bool,
+    pub authority: Pubkey,
+    pub withdraw_authority: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub initial_virtual_token_reserves: u64,
+    pub initial_virtual_sol_reserves: u64,
+    pub initial_real_token_reserves: u64,
+    pub token_total_supply: u64,
+    pub fee_basis_points: u16,
+    pub mint_fee_sol: u64,
+    pub trading_fee_creator_percent_sol: u16,
+    pub token_share_creator_percent: u16,
+    pub sol_share_first_buyer_after_raydium: u64, // not in percent
+    pub sol_share_instapump_after_raydium: u64,   // not in percent
+    pub graduation_sol_target: u64,               // 0 = graduate on token depletion
+    pub max_creator_initial_buy_bps: u16,         // of initial_real_token_reserves
+    pub creator_initial_buy_sets_first_buyer: bool,
+    pub launch_window_duration: u64,           // 0 = no launch window
+    pub launch_window_in_slots: bool,          // false = duration is in seconds
+    pub launch_window_max_buy_per_wallet: u64, // 0 = no cap
+    pub launch_window_fee_basis_points: u16,   // at creation, decays linearly to 0
+    pub launch_window_fee_to_creator: bool,    // false = fee goes to fee_recipient
+    pub auction_duration_seconds: u64,         // 0 = auction launches disabled
+    pub referral_tier_basis_points: [u16; REFERRAL_TIERS], // share of the admin fee
+    pub max_transfer_fee_basis_points: u16,    // 0 = transfer-fee launches disabled
+    pub max_transfer_fee: u64,                 // per transfer, in token base units
+    pub attesters: [Pubkey; MAX_ATTESTERS],    // default key = empty slot
+    pub platforms: [PlatformConfig; PLATFORM_COUNT], // indexed by SocialPlatform
+    pub direct_launch_min_sol: u64,
+    pub direct_launch_max_sol: u64, // 0 = direct launches disabled
+    pub metadata_update_window_seconds: u64, // 0 = metadata updates disabled
+    pub metadata_update_max_trades: u64, // 0 = no trade limit
+    pub collection_mint: Pubkey,    // default key = no collection yet
+    pub version: u8,                // 0 = written before versioning, see migrate_global
+}
+
+impl Global {
+    pub const LEN: usize = 1
+        + 32
+        + 32
+        + 32
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 8
+        + 1
+        + 8
+        + 8
+        + 1
+        + 8
+        + 2 * REFERRAL_TIERS
+        + 2
+        + 8
+        + 32 * MAX_ATTESTERS
+        + PlatformConfig::LEN * PLATFORM_COUNT
+        + 8
+        + 8
+        + 8
+        + 8
+        + 32
+        + 1;
+
+    /// Post ownership attestations are only required once an attester is set.
+    pub fn has_attesters(&self) -> bool {
+        self.attesters.iter().any(|key| *key != Pubkey::default())
+    }
+
+    /// Elevated launch window fee on `sol_cost`, decaying linearly with the
+    /// `remaining` part of the window.
+    pub fn launch_window_fee(&self, sol_cost: u64, remaining: u64) -> u64 {
+        (sol_cost as u128 * self.launch_window_fee_basis_points as u128 * remaining as u128
+            / (10000 * self.launch_window_duration as u128)) as u64
+    }
+
+    /// Referrer's share of `admin_fee` for the given tier.
+    pub fn referral_fee(&self, admin_fee: u64, tier: u8) -> u64 {
+        let basis_points = self
+            .referral_tier_basis_points
+            .get(tier as usize)
+            .copied()
+            .unwrap_or_default();
+        (admin_fee as u128 * basis_points as u128 / 10000) as u64
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct BondingCurve {
+    pub mint: Pubkey,
+    pub virtual_token_reserves: u64,
+    pub virtual_sol_reserves: u64,
+    pub real_token_reserves: u64,
+    pub real_sol_reserves: u64,
+    pub token_total_supply: u64,
+    pub complete: bool,
+    pub creator_address: Pubkey,
+    pub first_buyer_address: Pubkey,
+    pub graduation_sol_target: u64, // 0 = graduate on token depletion
+    pub created_slot: u64,
+    pub created_at: i64,
+    pub phase: CurvePhase,
+    pub auction_end_time: i64,
+    pub auction_total_bids: u64,
+    pub auction_tokens_sold: u64,
+    pub auction_sol_filled: u64, // SOL cost plus trading fees
+    pub auction_unclaimed_tokens: u64,
+    pub auction_unclaimed_refunds: u64,
+    pub trade_count: u64, // also the sequence number of the latest TradeEvent
+    pub buy_volume_sol: u64,
+    pub sell_volume_sol: u64,
+    pub buy_volume_tokens: u64,
+    pub sell_volume_tokens: u64,
+    pub fees_generated: u64,
+    pub unique_traders: u64,    // wallets with a UserPosition on this curve
+    pub price_cumulative: u128, // sum of price_per_token * seconds
+    pub last_price_update: i64,
+    pub observation_index: u8,
+    pub observations: [PriceObservation; TWAP_OBSERVATIONS],
+    pub price_history_initialized: bool, // buy and sell then require the PriceHistory
+    pub post_record: Pubkey,             // reverse lookup from the mint to its post
+    pub platform: SocialPlatform,
+    pub metadata_hash: [u8; 32], // SHA-256 of the metadata JSON at `uri`, zero if unset
+    pub metadata_locked: bool,
+    pub direct_launch_escrow: u64, // direct launch deposit, withdrawn only with the tokens
+    pub version: u8,               // 0 = written before versioning, see migrate_bonding_curve
+}
+
+impl BondingCurve {
+    pub const LEN: usize = 32
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 32
+        + 32
+        + 8
+        + 8
+        + 8
+        + 1
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 16
+        + 8
+        + 1
+        + PriceObservation::LEN * TWAP_OBSERVATIONS
+        + 1
+        + 32
+        + 1
+        + 32
+        + 1
+        + 8
+        + 1;
+
+    /// Spot price in the units of `price_per_token`.
+    pub fn spot_price(&self) -> u64 {
+        const SCALING_FACTOR: u128 = 100; // Must match calculate_price_and_sol
+        (self.virtual_sol_reserves as u128 * 1_000_000
+            / (self.virtual_token_reserves as u128 * SCALING_FACTOR)) as u64
+    }
+
+    pub fn start_price_accumulator(&mut self, timestamp: i64) {
+        self.last_price_update = timestamp;
+        self.observation_index = 0;
+        self.observations[0] = PriceObservation {
+            timestamp,
+            price_cumulative: 0,
+        };
+    }
+
+    /// Accumulates the current spot price for the time elapsed since the last
+    /// update. Must run before a trade changes the reserves.
+    pub fn update_price_accumulator(&mut self, timestamp: i64) -> Result<()> {
+        let elapsed = timestamp.saturating_sub(self.last_price_update).max(0) as u128;
+        self.price_cumulative = self
+            .price_cumulative
+            .checked_add(self.spot_price() as u128 * elapsed)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.last_price_update = timestamp;
+
+        // Only keep one observation per interval so the buffer spans a useful window
+        let latest = self.observations[self.observation_index as usize];
+        if timestamp.saturating_sub(latest.timestamp) >= TWAP_OBSERVATION_INTERVAL {
+            self.observation_index =
+                ((self.observation_index as usize + 1) % TWAP_OBSERVATIONS) as u8;
+            self.observations[self.observation_index as usize] = PriceObservation {
+                timestamp,
+                price_cumulative: self.price_cumulative,
+            };
+        }
+        Ok(())
+    }
+
+    /// Average price between the newest observation at least `window_seconds`
+    /// old and `now`.
+    pub fn twap(&self, window_seconds: u32, now: i64) -> Result<u64> {
+        require!(window_seconds > 0, ProgramError::InvalidTwapWindow);
+        let window_start = now.saturating_sub(window_seconds as i64);
+        let observation = self
+            .observations
+            .iter()
+            .filter(|observation| {
+                observation.timestamp != 0 && observation.timestamp <= window_start
+            })
+            .max_by_key(|observation| observation.timestamp)
+            .ok_or(ProgramError::InsufficientPriceHistory)?;
+
+        let elapsed_since_update = now.saturating_sub(self.last_price_update).max(0) as u128;
+        let price_cumulative = self
+            .price_cumulative
+            .checked_add(self.spot_price() as u128 * elapsed_since_update)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let elapsed = (now - observation.timestamp) as u128;
+
+        Ok(((price_cumulative - observation.price_cumulative) / elapsed) as u64)
+    }
+
+    /// Adds a trade to the curve statistics and returns its sequence number.
+    pub fn record_trade(
+        &mut self,
+        is_buy: bool,
+        sol_amount: u64,
+        token_amount: u64,
+        fees: u64,
+    ) -> Result<u64> {
+        let (volume_sol, volume_tokens) = if is_buy {
+            (&mut self.buy_volume_sol, &mut self.buy_volume_tokens)
+        } else {
+            (&mut self.sell_volume_sol, &mut self.sell_volume_tokens)
+        };
+        *volume_sol = volume_sol
+            .checked_add(sol_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        *volume_tokens = volume_tokens
+            .checked_add(token_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.fees_generated = self
+            .fees_generated
+            .checked_add(fees)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.trade_count = self
+            .trade_count
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(self.trade_count)
+    }
+
+    pub fn record_new_trader(&mut self) -> Result<()> {
+        self.unique_traders = self
+            .unique_traders
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Whether the creator is still inside the metadata update window and
+    /// under the trade limit, if one is set.
+    pub fn metadata_update_open(&self, global: &Global, now: i64) -> bool {
+        now < self
+            .created_at
+            .saturating_add(global.metadata_update_window_seconds as i64)
+            && (global.metadata_update_max_trades == 0
+                || self.trade_count < global.metadata_update_max_trades)
+    }
+
+    /// Part of the launch window still to run, in the window's unit (slots or
+    /// seconds), or None once it is over.
+    pub fn launch_window_remaining(&self, global: &Global, clock: &Clock) -> Option<u64> {
+        if global.launch_window_duration == 0 {
+            return None;
+        }
+        let elapsed = if global.launch_window_in_slots {
+            clock.slot.saturating_sub(self.created_slot)
+        } else {
+            clock.unix_timestamp.saturating_sub(self.created_at).max(0) as u64
+        };
+        global
+            .launch_window_duration
+            .checked_sub(elapsed)
+            .filter(|remaining| *remaining > 0)
+    }
+
+    /// Tokens that can still be bought before the curve graduates, either by
+    /// running out of `real_token_reserves` or by raising `graduation_sol_target`.
+    pub fn tokens_to_graduation(&self) -> Result<u64> {
+        if self.graduation_sol_target == 0 {
+            return Ok(self.real_token_reserves);
+        }
+        let remaining_sol = self
+            .graduation_sol_target
+            .saturating_sub(self.real_sol_reserves);
+        let tokens = calculate_tokens_for_sol(
+            remaining_sol,
+            self.virtual_token_reserves,
+            self.virtual_sol_reserves,
+        )?;
+        // `tokens` is the largest buy costing at most `remaining_sol`, which
+        // rounding can leave short of the target. One base unit more always
+        // reaches it, so dust below the target can't be bought for free.
+        Ok(tokens.saturating_add(1).min(self.real_token_reserves))
+    }
+
+    pub fn apply_buy(
+        &mut self,
+        token_amount: u64,
+        sol_cost: u64,
+        new_virtual_token_reserves: u64,
+        new_virtual_sol_reserves: u64,
+    ) -> Result<()> {
+        self.virtual_token_reserves = new_virtual_token_reserves;
+        self.virtual_sol_reserves = new_virtual_sol_reserves;
+        self.real_token_reserves = self
+            .real_token_reserves
+            .checked_sub(token_amount)
+            .ok_or(ProgramError::InsufficientTokens)?;
+        self.real_sol_reserves = self
+            .real_sol_reserves
+            .checked_add(sol_cost)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    pub fn graduation_reached(&self) -> bool {
+        self.real_token_reserves == 0
+            || (self.graduation_sol_target > 0
+                && self.real_sol_reserves >= self.graduation_sol_target)
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct Stats {
+    pub total_launches: u64,
+    pub total_graduations: u64,
+    pub total_trades: u64,
+    pub total_volume_sol: u64,
+    pub total_fees_collected: u64, // mint fees and the protocol's share of trading fees
+}
+
+impl Stats {
+    pub const LEN: usize = 8 + 8 + 8 + 8 + 8;
+
+    pub fn record_launch(&mut self, mint_fee: u64) -> Result<()> {
+        self.total_launches = self
+            .total_launches
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.total_fees_collected = self
+            .total_fees_collected
+            .checked_add(mint_fee)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_trade(&mut self, sol_amount: u64, protocol_fees: u64) -> Result<()> {
+        self.total_trades = self
+            .total_trades
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.total_volume_sol = self
+            .total_volume_sol
+            .checked_add(sol_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.total_fees_collected = self
+            .total_fees_collected
+            .checked_add(protocol_fees)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_graduation(&mut self) -> Result<()> {
+        self.total_graduations = self
+            .total_graduations
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+}
+
+pub const TWAP_OBSERVATIONS: usize = 16;
+pub const TWAP_OBSERVATION_INTERVAL: i64 = 60; // seconds
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct PriceObservation {
+    pub timestamp: i64,
+    pub price_cumulative: u128,
+}
+
+impl PriceObservation {
+    pub const LEN: usize = 8 + 16;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct Candle {
+    pub start_time: i64,
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+    pub volume_sol: u64,
+    pub volume_tokens: u64,
+}
+
+impl Candle {
+    pub const LEN: usize = 8 + 8 + 8 + 8 + 8 + 8 + 8;
+}
+
+/// Fixed-capacity ring buffer of candles. Candles are appended until
+/// `capacity` is reached, then `head` points at the oldest one, which is
+/// overwritten next.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+pub struct CandleBuffer {
+    pub capacity: u32,
+    pub head: u32,
+    pub candles: Vec<Candle>,
+}
+
+impl CandleBuffer {
+    pub fn space(capacity: u32) -> usize {
+        4 + 4 + 4 + Candle::LEN * capacity as usize
+    }
+
+    fn latest_mut(&mut self) -> Option<&mut Candle> {
+        if self.candles.len() < self.capacity as usize {
+            self.candles.last_mut()
+        } else {
+            let index = (self.head as usize + self.candles.len() - 1) % self.candles.len();
+            self.candles.get_mut(index)
+        }
+    }
+
+    pub fn record_trade(
+        &mut self,
+        period: i64,
+        timestamp: i64,
+        price_before: u64,
+        price_after: u64,
+        volume_sol: u64,
+        volume_tokens: u64,
+    ) {
+        let start_time = timestamp - timestamp.rem_euclid(period);
+        if let Some(candle) = self.latest_mut() {
+            if candle.start_time == start_time {
+                candle.high = candle.high.max(price_before).max(price_after);
+                candle.low = candle.low.min(price_before).min(price_after);
+                candle.close = price_after;
+                candle.volume_sol = candle.volume_sol.saturating_add(volume_sol);
+                candle.volume_tokens = candle.volume_tokens.saturating_add(volume_tokens);
+                return;
+            }
+        }
+
+        let candle = Candle {
+            start_time,
+            open: price_before,
+            high: price_before.max(price_after),
+            low: price_before.min(price_after),
+            close: price_after,
+            volume_sol,
+            volume_tokens,
+        };
+        if self.candles.len() < self.capacity as usize {
+            self.candles.push(candle);
+        } else {
+            self.candles[self.head as usize] = candle;
+            self.head = (self.head + 1) % self.capacity;
+        }
+    }
+
+    pub fn grow(&mut self, capacity: u32) -> Result<()> {
+        require!(
+            capacity >= self.capacity,
+            ProgramError::InvalidPriceHistoryCapacity
+        );
+        // Put the oldest candle first again so appending keeps the order
+        let head = self.head as usize;
+        self.candles.rotate_left(head);
+        self.head = 0;
+        self.capacity = capacity;
+        Ok(())
+    }
+}
+
+/// Cap on the candles of both buffers together. It keeps the account within
+/// the 10 KiB a CPI can allocate, and keeps deserializing it on every trade
+/// well inside the heap.
+pub const MAX_PRICE_HISTORY_CANDLES: u32 = 180;
+
+#[account]
+#[derive(Default)]
+pub struct PriceHistory {
+    pub bonding_curve: Pubkey,
+    pub minute: CandleBuffer,
+    pub hour: CandleBuffer,
+}
+
+impl PriceHistory {
+    pub fn space(minute_capacity: u32, hour_capacity: u32) -> usize {
+        32 + CandleBuffer::space(minute_capacity) + CandleBuffer::space(hour_capacity)
+    }
+
+    pub fn check_capacity(minute_capacity: u32, hour_capacity: u32) -> Result<()> {
+        require!(
+            minute_capacity as u64 + hour_capacity as u64 <= MAX_PRICE_HISTORY_CANDLES as u64,
+            ProgramError::InvalidPriceHistoryCapacity
+        );
+        Ok(())
+    }
+
+    pub fn record_trade(
+        &mut self,
+        timestamp: i64,
+        price_before: u64,
+        price_after: u64,
+        volume_sol: u64,
+        volume_tokens: u64,
+    ) {
+        self.minute.record_trade(
+            60,
+            timestamp,
+            price_before,
+            price_after,
+            volume_sol,
+            volume_tokens,
+        );
+        self.hour.record_trade(
+            3600,
+            timestamp,
+            price_before,
+            price_after,
+            volume_sol,
+            volume_tokens,
+        );
+    }
+}
+
+pub const REFERRAL_TIERS: usize = 4;
+
+#[account]
+#[derive(Default)]
+pub struct Referrer {
+    pub authority: Pubkey,
+    pub tier: u8,
+    pub total_earned: u64,
+    pub total_claimed: u64,
+}
+
+impl Referrer {
+    pub const LEN: usize = 32 + 1 + 8 + 8;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum CurvePhase {
+    #[default]
+    Trading,
+    Auction,
+}
+
+pub const MAX_POST_ID_LEN: usize = 200; // canonical form, see canonical_post_id
+
+/// One per post, so a post can only ever be launched once.
+#[account]
+#[derive(Default)]
+pub struct PostRecord {
+    pub platform: SocialPlatform,
+    pub post_id: String,
+    pub mint: Pubkey,
+    pub creator: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub created_slot: u64,
+    pub status: LaunchStatus,
+}
+
+impl PostRecord {
+    pub const LEN: usize = 1 + 4 + MAX_POST_ID_LEN + 32 + 32 + 32 + 8 + 1;
+}
+
+pub const PLATFORM_COUNT: usize = 5;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum SocialPlatform {
+    #[default]
+    Instagram,
+    TikTok,
+    X,
+    YouTube,
+    GenericUrl,
+}
+
+impl SocialPlatform {
+    /// Hosts of the platform's post URLs, after `www.` or `m.`.
+    fn hosts(&self) -> &'static [&'static str] {
+        match self {
+            SocialPlatform::Instagram => &["instagram.com/", "instagr.am/"],
+            SocialPlatform::TikTok => &["tiktok.com/"],
+            SocialPlatform::X => &["x.com/", "twitter.com/"],
+            SocialPlatform::YouTube => &["youtube.com/", "youtu.be/"],
+            SocialPlatform::GenericUrl => &[],
+        }
+    }
+
+    /// Path segments that come right before the post ID in post URLs.
+    fn id_markers(&self) -> &'static [&'static str] {
+        match self {
+            SocialPlatform::Instagram => &["p", "reel", "reels", "tv"],
+            SocialPlatform::TikTok => &["video", "photo"],
+            SocialPlatform::X => &["status"],
+            SocialPlatform::YouTube => &["shorts", "live", "embed"],
+            SocialPlatform::GenericUrl => &[],
+        }
+    }
+
+    fn is_valid_id(&self, id: &str) -> bool {
+        match self {
+            SocialPlatform::TikTok | SocialPlatform::X => id.bytes().all(|b| b.is_ascii_digit()),
+            _ => id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'),
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct PlatformConfig {
+    pub enabled: bool,
+    pub mint_fee_sol: u64, // charged on top of Global.mint_fee_sol
+}
+
+impl PlatformConfig {
+    pub const LEN: usize = 1 + 8;
+}
+
+/// Launch parameters of `create` besides the token metadata and the post.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct CreateArgs {
+    pub post_id_hash: [u8; 32], // hash_post_id of the canonical post ID, the post record seed
+    pub metadata_hash: Option<[u8; 32]>, // SHA-256 of the metadata JSON at `uri`
+    pub initial_buy_amount: Option<u64>, // creator buy in the same instruction
+    pub max_sol_cost: Option<u64>, // slippage bound of the initial buy
+    pub auction: bool,          // open with a batch auction instead of the curve
+    pub direct_launch: bool,    // skip the curve and go straight to migration
+    pub direct_launch_sol: u64, // creator SOL deposited for a direct launch
+    pub transfer_fee_basis_points: u16, // 0 = no transfer fee
+    pub maximum_transfer_fee: u64,
+    pub attestation_expiry: i64, // only checked once attesters are set
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum LaunchStatus {
+    #[default]
+    Active, // on the bonding curve, complete or not
+    Migrated,
 }
 
 #[account]
 #[derive(Default)]
-pub struct Global {
-    pub initialized: bool,
-    pub authority: Pubkey,
-    pub withdraw_authority: Pubkey,
-    pub fee_recipient: Pubkey,
-    pub initial_virtual_token_reserves: u64,
-    pub initial_virtual_sol_reserves: u64,
-    pub initial_real_token_reserves: u64,
-    pub token_total_supply: u64,
-    pub fee_basis_points: u16,
-    pub mint_fee_sol: u64,
-    pub trading_fee_creator_percent_sol: u16,
-    pub token_share_creator_percent: u16,
-    pub sol_share_first_buyer_after_raydium: u64, // not in percent
-    pub sol_share_instapump_after_raydium: u64,   // not in percent
+pub struct AuctionBid {
+    pub bonding_curve: Pubkey,
+    pub user: Pubkey,
+    pub sol_amount: u64,
 }
 
-impl Global {
-    pub const LEN: usize = 1 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8;
+impl AuctionBid {
+    pub const LEN: usize = 32 + 32 + 8;
 }
 
 #[account]
 #[derive(Default)]
-pub struct BondingCurve {
-    pub mint: Pubkey,
-    pub virtual_token_reserves: u64,
-    pub virtual_sol_reserves: u64,
-    pub real_token_reserves: u64,
-    pub real_sol_reserves: u64,
-    pub token_total_supply: u64,
-    pub complete: bool,
-    pub creator_address: Pubkey,
-    pub first_buyer_address: Pubkey,
+pub struct UserPosition {
+    pub bonding_curve: Pubkey,
+    pub user: Pubkey,
+    pub token_balance: u64, // tokens bought minus tokens sold through the curve
+    pub tokens_bought: u64,
+    pub tokens_sold: u64,
+    pub sol_spent: u64,
+    pub sol_received: u64,
+    pub fees_paid: u64,
+    pub cost_basis: u64, // SOL paid for token_balance, fees included
+    pub realized_pnl: i64,
+    pub first_trade_at: i64,
+    pub last_trade_at: i64,
 }
 
-impl BondingCurve {
-    pub const LEN: usize = 32 + 8 + 8 + 8 + 8 + 8 + 1 + 32 + 32;
+impl UserPosition {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8;
+
+    /// Average cost of the current balance, in lamports per whole token
+    /// (scaled like `price_per_token`).
+    pub fn average_cost_basis(&self) -> u64 {
+        if self.token_balance == 0 {
+            return 0;
+        }
+        (self.cost_basis as u128 * 1_000_000 / self.token_balance as u128) as u64
+    }
+
+    pub fn record_buy(
+        &mut self,
+        token_amount: u64,
+        sol_amount: u64,
+        fees: u64,
+        timestamp: i64,
+    ) -> Result<()> {
+        self.token_balance = self
+            .token_balance
+            .checked_add(token_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.tokens_bought = self
+            .tokens_bought
+            .checked_add(token_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.sol_spent = self
+            .sol_spent
+            .checked_add(sol_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.fees_paid = self
+            .fees_paid
+            .checked_add(fees)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.cost_basis = self
+            .cost_basis
+            .checked_add(sol_amount)
+            .and_then(|cost| cost.checked_add(fees))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.record_timestamp(timestamp);
+        Ok(())
+    }
+
+    pub fn record_sell(
+        &mut self,
+        token_amount: u64,
+        sol_amount: u64,
+        fees: u64,
+        timestamp: i64,
+    ) -> Result<()> {
+        // Tokens received outside the curve have no cost basis, so selling
+        // more than the tracked balance releases all of it
+        let cost_released = if token_amount >= self.token_balance {
+            self.cost_basis
+        } else {
+            (self.cost_basis as u128 * token_amount as u128 / self.token_balance as u128) as u64
+        };
+        self.cost_basis -= cost_released;
+        self.token_balance = self.token_balance.saturating_sub(token_amount);
+        self.realized_pnl = self
+            .realized_pnl
+            .checked_add(sol_amount as i64 - fees as i64 - cost_released as i64)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.tokens_sold = self
+            .tokens_sold
+            .checked_add(token_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.sol_received = self
+            .sol_received
+            .checked_add(sol_amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.fees_paid = self
+            .fees_paid
+            .checked_add(fees)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        self.record_timestamp(timestamp);
+        Ok(())
+    }
+
+    fn record_timestamp(&mut self, timestamp: i64) {
+        if self.first_trade_at == 0 {
+            self.first_trade_at = timestamp;
+        }
+        self.last_trade_at = timestamp;
+    }
 }
 
 #[error_code]
@@ -979,60 +3989,615 @@ pub enum ProgramError {
     SlippageExceeded,
     #[msg("The program is facing InsufficientTokens.")]
     InsufficientTokens,
-    #[msg("This Insta post ID has already been used to create a token.")]
+    #[msg("This post has already been used to create a token.")]
     PostIdAlreadyUsed,
+    #[msg("The creator initial buy exceeds max_creator_initial_buy_bps.")]
+    CreatorInitialBuyTooLarge,
+    #[msg("The buy exceeds the per-wallet cap of the launch window.")]
+    LaunchWindowBuyCapExceeded,
+    #[msg("The launch window fee must not exceed 10000 basis points.")]
+    InvalidLaunchWindowFee,
+    #[msg("Auction launches are disabled.")]
+    AuctionDisabled,
+    #[msg("An auction launch can't include a creator initial buy.")]
+    InitialBuyDuringAuction,
+    #[msg("The bonding curve is still in its opening auction.")]
+    AuctionInProgress,
+    #[msg("The bonding curve has no active auction.")]
+    AuctionNotActive,
+    #[msg("The auction has ended.")]
+    AuctionEnded,
+    #[msg("The auction has not ended yet.")]
+    AuctionNotEnded,
+    #[msg("The auction has not been settled yet.")]
+    AuctionNotSettled,
+    #[msg("Referral tier rates must be between 0 and 10000 basis points.")]
+    InvalidReferralTier,
+    #[msg("A trader can't refer themselves.")]
+    SelfReferral,
+    #[msg("The TWAP window must be greater than zero.")]
+    InvalidTwapWindow,
+    #[msg("The price history doesn't cover the requested TWAP window.")]
+    InsufficientPriceHistory,
+    #[msg("Price history capacities must be non-zero, within the cap, and can only grow.")]
+    InvalidPriceHistoryCapacity,
+    #[msg("The token account is not the recipient's associated token account.")]
+    InvalidRecipientTokenAccount,
+    #[msg("Legacy SPL Token launches need the Metaplex program and metadata accounts.")]
+    MetadataAccountsRequired,
+    #[msg("Transfer fees are only available on Token-2022 launches.")]
+    TransferFeeRequiresToken2022,
+    #[msg("The transfer fee exceeds 10000 basis points or the bounds set in Global.")]
+    InvalidTransferFee,
+    #[msg("The post ID is not a valid post ID or URL for the platform.")]
+    InvalidPostId,
+    #[msg("create must follow an ed25519 attestation instruction.")]
+    MissingAttestation,
+    #[msg("The attestation doesn't match this post, creator, expiry and nonce.")]
+    InvalidAttestation,
+    #[msg("The attestation was not signed by a configured attester.")]
+    UnknownAttester,
+    #[msg("The attestation has expired.")]
+    AttestationExpired,
+    #[msg("Launches from this platform are disabled.")]
+    PlatformDisabled,
+    #[msg("Direct launches are disabled.")]
+    DirectLaunchDisabled,
+    #[msg("A direct launch can't include an auction or a creator initial buy.")]
+    DirectLaunchConflict,
+    #[msg("The direct launch SOL is outside the bounds set in Global.")]
+    DirectLaunchSolOutOfBounds,
+    #[msg("The name must be 1 to 32 bytes without control characters.")]
+    InvalidName,
+    #[msg("The symbol must be 1 to 10 printable ASCII characters.")]
+    InvalidSymbol,
+    #[msg("The URI must be at most 200 bytes and use https, ipfs or ar.")]
+    InvalidUri,
+    #[msg("The token metadata has been locked.")]
+    MetadataLocked,
+    #[msg("The metadata update window or trade limit has passed.")]
+    MetadataUpdateWindowClosed,
+    #[msg("Launches must pass the collection accounts once the collection exists.")]
+    CollectionAccountsRequired,
+    #[msg("The account is already at the current layout version.")]
+    AlreadyMigrated,
+    #[msg("post_id_hash is not the SHA-256 of the canonical post ID.")]
+    PostIdHashMismatch,
+    #[msg("The curve has a price history, which must be passed to every trade.")]
+    PriceHistoryRequired,
+    #[msg("max_creator_initial_buy_bps must be at most 10000.")]
+    InvalidCreatorInitialBuyBps,
+    #[msg("Instagram launches need the previous version's marker account of the post.")]
+    InvalidLegacyPostAccount,
+    #[msg("The curve's post record must be passed.")]
+    InvalidPostRecord,
+}
+
+// Event definitions
+#[event]
+pub struct CreateEvent {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub metadata_hash: Option<[u8; 32]>,
+    pub platform: SocialPlatform,
+    pub post_id: String,
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub user: Pubkey,
+    pub direct_launch: bool,
+    pub transfer_fee_basis_points: u16, // 0 = no transfer fee
+    pub maximum_transfer_fee: u64,
+}
+
+pub const TRADE_EVENT_VERSION: u8 = 4;
+pub const WITHDRAW_EVENT_VERSION: u8 = 4;
+
+/// Every SOL and token movement of a trade. Fees are added to `sol_amount`
+/// on buys and deducted from it on sells; reserves are the values after the
+/// trade. `token_amount` is the amount transferred and `tokens_received` what
+/// arrived after any Token-2022 transfer fee, on buys and sells alike.
+#[event]
+pub struct TradeEvent {
+    pub version: u8,
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub creator: Pubkey,
+    pub sol_amount: u64,
+    pub token_amount: u64,
+    pub tokens_received: u64,
+    pub price_per_token: u64,
+    pub is_buy: bool,
+    pub is_creator_buy: bool,
+    pub user: Pubkey,
+    pub recipient: Pubkey, // wallet the bought tokens went to; `user` on sells
+    pub admin_fee: u64,    // after the referrer's share
+    pub creator_fee: u64,
+    pub launch_window_fee: u64,
+    pub referrer: Pubkey,
+    pub referrer_fee: u64,
+    pub trade_sequence: u64,
+    pub timestamp: i64,
+    pub virtual_sol_reserves: u64,
+    pub virtual_token_reserves: u64,
+    pub real_sol_reserves: u64,
+    pub real_token_reserves: u64,
+}
+
+#[event]
+pub struct CompleteEvent {
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub timestamp: i64,
+    pub virtual_sol_reserves: u64,
+    pub virtual_token_reserves: u64,
+    pub real_sol_reserves: u64,
+    pub real_token_reserves: u64,
+}
+
+#[event]
+pub struct WithdrawEvent {
+    pub version: u8,
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub sol_amount: u64,
+    pub token_amount: u64,
+    pub creator: Pubkey,
+    pub creator_token_share: u64,
+    pub first_buyer: Pubkey,
+    pub first_buyer_sol_share: u64,
+    pub instapump_sol_share: u64,
+    pub direct_launch_sol: u64, // part of sol_amount that is a direct launch deposit
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SetParamsEvent {
+    pub withdraw_authority: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub initial_virtual_token_reserves: u64,
+    pub initial_virtual_sol_reserves: u64,
+    pub initial_real_token_reserves: u64,
+    pub token_total_supply: u64,
+    pub fee_basis_points: u16,
+    pub mint_fee_sol: u64,
+}
+
+#[event]
+pub struct SetCreatorInitialBuyEvent {
+    pub max_bps: u16,
+    pub sets_first_buyer: bool,
+}
+
+#[event]
+pub struct SetGraduationTargetEvent {
+    pub graduation_sol_target: u64,
+}
+
+#[event]
+pub struct SetLaunchWindowEvent {
+    pub duration: u64,
+    pub in_slots: bool,
+    pub max_buy_per_wallet: u64,
+    pub fee_basis_points: u16,
+    pub fee_to_creator: bool,
+}
+
+#[event]
+pub struct SetReferralTiersEvent {
+    pub tier_basis_points: [u16; REFERRAL_TIERS],
+}
+
+#[event]
+pub struct RegisterReferrerEvent {
+    pub referrer: Pubkey,
+    pub authority: Pubkey,
+}
+
+#[event]
+pub struct SetReferrerTierEvent {
+    pub referrer: Pubkey,
+    pub authority: Pubkey,
+    pub tier: u8,
+}
+
+#[event]
+pub struct ReferralClaimEvent {
+    pub referrer: Pubkey,
+    pub authority: Pubkey,
+    pub sol_amount: u64,
+    pub total_claimed: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SetTransferFeeBoundsEvent {
+    pub max_basis_points: u16,
+    pub max_fee: u64,
+}
+
+#[event]
+pub struct SetDirectLaunchBoundsEvent {
+    pub min_sol: u64,
+    pub max_sol: u64,
+}
+
+#[event]
+pub struct SetMetadataUpdateLimitsEvent {
+    pub window_seconds: u64,
+    pub max_trades: u64,
+}
+
+#[event]
+pub struct SetPlatformConfigEvent {
+    pub platform: SocialPlatform,
+    pub enabled: bool,
+    pub mint_fee_sol: u64,
+}
+
+#[event]
+pub struct SetAttestersEvent {
+    pub attesters: [Pubkey; MAX_ATTESTERS],
+}
+
+#[event]
+pub struct SetAuctionParamsEvent {
+    pub duration_seconds: u64,
+}
+
+#[event]
+pub struct AuctionBidEvent {
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub user: Pubkey,
+    pub sol_amount: u64,
+    pub total_bids: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuctionSettledEvent {
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub total_bids: u64,
+    pub token_amount: u64,
+    pub sol_filled: u64,
+    pub price_per_token: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AuctionClaimEvent {
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub user: Pubkey,
+    pub token_amount: u64,
+    pub sol_spent: u64,
+    pub sol_refund: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct HarvestTransferFeesEvent {
+    pub mint: Pubkey,
+    pub creator: Pubkey,
+    pub token_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MetadataUpdateEvent {
+    pub mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub metadata_hash: Option<[u8; 32]>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MetadataLockedEvent {
+    pub mint: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InitCollectionEvent {
+    pub collection_mint: Pubkey,
+    pub collection_authority: Pubkey,
+}
+
+#[event]
+pub struct MigrateGlobalEvent {
+    pub from_version: u8,
+    pub version: u8,
+}
+
+#[event]
+pub struct MigrateBondingCurveEvent {
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub from_version: u8,
+    pub version: u8,
+}
+
+fn transfer_sol<'info>(
+    from: AccountInfo<'info>,
+    to: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    lamports: u64,
+) -> Result<()> {
+    let ix = system_instruction::transfer(from.key, to.key, lamports);
+    anchor_lang::solana_program::program::invoke(&ix, &[from, to, system_program])?;
+    Ok(())
+}
+
+/// Grows an account owned by this program to `len` bytes, zero-filling the
+/// new space and topping up its rent-exempt balance from `payer`.
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    len: usize,
+) -> Result<()> {
+    if account.data_len() >= len {
+        return Ok(());
+    }
+    let top_up = Rent::get()?
+        .minimum_balance(len)
+        .saturating_sub(account.lamports());
+    if top_up > 0 {
+        transfer_sol(payer, account.clone(), system_program, top_up)?;
+    }
+    account.realloc(len, true)?;
+    Ok(())
+}
+
+/// Moves lamports out of an account owned by this program, without a CPI.
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> Result<()> {
+    if lamports == 0 {
+        return Ok(());
+    }
+    let from_lamports = from
+        .lamports()
+        .checked_sub(lamports)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    let to_lamports = to
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
+
+/// Token-2022 transfer fee withheld from a transfer of `amount`; 0 for mints
+/// without the transfer-fee extension.
+fn transfer_fee(mint: &AccountInfo, epoch: u64, amount: u64) -> Result<u64> {
+    let mint_data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<MintState>::unpack(&mint_data)?;
+    match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(config) => Ok(config
+            .calculate_epoch_fee(epoch, amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Canonical form of a post reference on `platform`.
+///
+/// For the social platforms this is the bare post ID: the shortcode, video or
+/// status ID, given either on its own or as a post URL on one of the
+/// platform's hosts. URLs must name the post through an ID marker such as
+/// `/p/` or `/status/`, so profile and foreign URLs are rejected. The scheme,
+/// `www.`, host casing, username prefix, query string, fragment and trailing
+/// slashes are ignored; IDs are case-sensitive, so their case is kept. Generic
+/// URLs keep their path and query, with the scheme and host normalized.
+/// Clients must apply the same normalization before hashing with
+/// [`hash_post_id`].
+pub fn canonical_post_id(platform: SocialPlatform, post_id: &str) -> Result<String> {
+    let post_id = post_id.trim();
+    if platform == SocialPlatform::GenericUrl {
+        return canonical_url(post_id);
+    }
+
+    let post_id = post_id.split('#').next().unwrap_or_default();
+    let (reference, query) = post_id.split_once('?').unwrap_or((post_id, ""));
+    let reference = reference.trim_end_matches('/');
+
+    // Anything with a path is a URL; only a bare ID can skip the host
+    let id = if reference.contains('/') {
+        post_id_from_url(platform, reference, query)?
+    } else {
+        reference
+    };
+
+    require!(
+        !id.is_empty() && id.len() <= MAX_POST_ID_LEN && platform.is_valid_id(id),
+        ProgramError::InvalidPostId
+    );
+    Ok(id.to_string())
 }
 
-// Event definitions
-#[event]
-pub struct CreateEvent {
-    pub name: String,
-    pub symbol: String,
-    pub uri: String,
-    pub post_id: String,
-    pub mint: Pubkey,
-    pub bonding_curve: Pubkey,
-    pub user: Pubkey,
-    pub direct_launch: bool,
+/// Post ID named by a post URL on one of the platform's hosts, given without
+/// its query string. Empty if the URL has no ID after its marker.
+fn post_id_from_url<'a>(platform: SocialPlatform, url: &'a str, query: &'a str) -> Result<&'a str> {
+    let mut url = url;
+    if let Some((scheme, rest)) = url.split_once("://") {
+        require!(
+            scheme.eq_ignore_ascii_case("https") || scheme.eq_ignore_ascii_case("http"),
+            ProgramError::InvalidPostId
+        );
+        url = rest;
+    }
+    for prefix in ["www.", "m."] {
+        if starts_with_ignore_case(url, prefix) {
+            url = &url[prefix.len()..];
+        }
+    }
+    let host = platform
+        .hosts()
+        .iter()
+        .find(|host| starts_with_ignore_case(url, host))
+        .ok_or(ProgramError::InvalidPostId)?;
+    let segments: Vec<&str> = url[host.len()..].split('/').collect();
+
+    match (platform, *host, segments.as_slice()) {
+        // YouTube watch URLs carry the video ID in the query string
+        (SocialPlatform::YouTube, _, [watch]) if watch.eq_ignore_ascii_case("watch") => {
+            let video_id = query.split('&').find_map(|param| param.strip_prefix("v="));
+            Ok(video_id.unwrap_or_default())
+        }
+        // Short links carry the video ID as their first path segment
+        (SocialPlatform::YouTube, "youtu.be/", [video_id, ..]) => Ok(video_id),
+        _ => {
+            let marker = segments
+                .iter()
+                .position(|segment| {
+                    platform
+                        .id_markers()
+                        .iter()
+                        .any(|marker| segment.eq_ignore_ascii_case(marker))
+                })
+                .ok_or(ProgramError::InvalidPostId)?;
+            Ok(segments.get(marker + 1).copied().unwrap_or_default())
+        }
+    }
 }
 
-#[event]
-pub struct TradeEvent {
-    pub mint: Pubkey,
-    pub sol_amount: u64,
-    pub token_amount: u64,
-    pub is_buy: bool,
-    pub user: Pubkey,
-    pub timestamp: i64,
-    pub virtual_sol_reserves: u64,
-    pub virtual_token_reserves: u64,
+/// `https://<lowercase host without www.><path>[?<query>]`, without a
+/// fragment or trailing slashes on the path.
+fn canonical_url(url: &str) -> Result<String> {
+    let url = url.split('#').next().unwrap_or_default();
+    let (url, query) = url.split_once('?').unwrap_or((url, ""));
+    let url = url.trim_end_matches('/');
+    let (scheme, rest) = url.split_once("://").ok_or(ProgramError::InvalidPostId)?;
+    require!(
+        scheme.eq_ignore_ascii_case("https") || scheme.eq_ignore_ascii_case("http"),
+        ProgramError::InvalidPostId
+    );
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let host = host.to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    let canonical = if query.is_empty() {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}{}?{}", host, path, query)
+    };
+    require!(
+        !host.is_empty()
+            && canonical.len() <= MAX_POST_ID_LEN
+            && canonical.bytes().all(|b| b.is_ascii_graphic()),
+        ProgramError::InvalidPostId
+    );
+    Ok(canonical)
 }
 
-#[event]
-pub struct CompleteEvent {
-    pub mint: Pubkey,
-    pub bonding_curve: Pubkey,
-    pub timestamp: i64,
+fn starts_with_ignore_case(value: &str, prefix: &str) -> bool {
+    value.len() >= prefix.len()
+        && value.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
 }
 
-#[event]
-pub struct WithdrawEvent {
-    pub mint: Pubkey,
-    pub sol_amount: u64,
-    pub token_amount: u64,
-    pub timestamp: i64,
+/// Checks the token metadata against the Metaplex length limits, so bad input
+/// fails up front instead of deep inside a CPI.
+fn validate_metadata(name: &str, symbol: &str, uri: &str) -> Result<()> {
+    require!(
+        !name.trim().is_empty()
+            && name.len() <= MAX_NAME_LEN
+            && !name.chars().any(char::is_control),
+        ProgramError::InvalidName
+    );
+    require!(
+        !symbol.is_empty()
+            && symbol.len() <= MAX_SYMBOL_LEN
+            && symbol.bytes().all(|b| b.is_ascii_graphic()),
+        ProgramError::InvalidSymbol
+    );
+    require!(
+        uri.len() <= MAX_URI_LEN
+            && uri.bytes().all(|b| b.is_ascii_graphic())
+            && ALLOWED_URI_SCHEMES
+                .iter()
+                .any(|scheme| uri.len() > scheme.len() && starts_with_ignore_case(uri, scheme)),
+        ProgramError::InvalidUri
+    );
+    Ok(())
 }
 
-#[event]
-pub struct SetParamsEvent {
-    pub withdraw_authority: Pubkey,
-    pub fee_recipient: Pubkey,
-    pub initial_virtual_token_reserves: u64,
-    pub initial_virtual_sol_reserves: u64,
-    pub initial_real_token_reserves: u64,
-    pub token_total_supply: u64,
-    pub fee_basis_points: u16,
-    pub mint_fee_sol: u64,
+/// SHA-256 of a canonical post ID, the seed of its post record.
+pub fn hash_post_id(canonical_post_id: &str) -> [u8; 32] {
+    hash(canonical_post_id.as_bytes()).to_bytes()
+}
+
+/// Message an attester signs to vouch that `creator` owns the post. It names
+/// the mint being launched, and a mint can only be created once, so the
+/// signature can't be replayed for another launch.
+pub fn attestation_message(
+    platform: SocialPlatform,
+    post_id_hash: &[u8; 32],
+    creator: &Pubkey,
+    mint: &Pubkey,
+    expiry: i64,
+) -> Vec<u8> {
+    [
+        ATTESTATION_DOMAIN,
+        &[platform as u8][..],
+        &post_id_hash[..],
+        creator.as_ref(),
+        mint.as_ref(),
+        &expiry.to_le_bytes()[..],
+    ]
+    .concat()
+}
+
+/// Checks that the instruction right before the current one is an ed25519
+/// signature check of `message` by one of the `attesters`. The ed25519
+/// program has already failed the transaction if the signature is invalid.
+fn verify_attestation(
+    instructions: &AccountInfo,
+    attesters: &[Pubkey],
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions)?;
+    require!(current_index > 0, ProgramError::MissingAttestation);
+    let ix = load_instruction_at_checked(current_index as usize - 1, instructions)?;
+    require!(
+        ix.program_id == ed25519_program::ID && ix.accounts.is_empty(),
+        ProgramError::MissingAttestation
+    );
+
+    // A single signature, whose key and message live in the same instruction
+    let data = &ix.data;
+    require!(
+        data.len() >= 16 && data[0] == 1,
+        ProgramError::InvalidAttestation
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+    require!(
+        [read_u16(4), read_u16(8), read_u16(14)]
+            .iter()
+            .all(|index| *index == u16::MAX as usize),
+        ProgramError::InvalidAttestation
+    );
+    let public_key_offset = read_u16(6);
+    let message_offset = read_u16(10);
+    let message_size = read_u16(12);
+    let public_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or(ProgramError::InvalidAttestation)?;
+    let signed_message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(ProgramError::InvalidAttestation)?;
+
+    require!(
+        attesters
+            .iter()
+            .any(|attester| *attester != Pubkey::default() && attester.as_ref() == public_key),
+        ProgramError::UnknownAttester
+    );
+    require!(signed_message == message, ProgramError::InvalidAttestation);
+    Ok(())
 }
 
 fn calculate_price_and_sol(
@@ -1125,3 +4690,900 @@ fn calculate_price_and_sol_sell_operation(
         new_virtual_sol_reserves,
     ))
 }
+
+fn calculate_tokens_for_sol(
+    sol_amount: u64,
+    virtual_token_reserves: u64,
+    virtual_sol_reserves: u64,
+) -> Result<u64> {
+    const SCALING_FACTOR: u128 = 100; // Must match calculate_price_and_sol
+
+    // Calculate the constant product k
+    let k = (virtual_token_reserves as u128) * (virtual_sol_reserves as u128);
+
+    // Calculate new virtual SOL reserves after spending `sol_amount`
+    let new_virtual_sol_reserves = (virtual_sol_reserves as u128)
+        .checked_add((sol_amount as u128) * SCALING_FACTOR)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    // Round the new token reserves up so the returned amount never costs more
+    // than `sol_amount` when priced by calculate_price_and_sol
+    let new_virtual_token_reserves = k
+        .checked_add(new_virtual_sol_reserves - 1)
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        / new_virtual_sol_reserves;
+
+    let token_amount = (virtual_token_reserves as u128)
+        .checked_sub(new_virtual_token_reserves)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    Ok(token_amount as u64)
+}
+
+/// SOL left for the withdraw authority once the first buyer's and instapump's
+/// shares are paid out of `available_lamports`. The curve keeps its
+/// rent-exempt `minimum_balance` plus a 10000 lamport margin.
+fn withdraw_sol_amount(
+    available_lamports: u64,
+    minimum_balance: u64,
+    first_buyer_share: u64,
+    instapump_share: u64,
+) -> u64 {
+    available_lamports
+        .saturating_sub(minimum_balance + 10000)
+        .saturating_sub(first_buyer_share)
+        .saturating_sub(instapump_share)
+}
+
+/// Demand of `total_bids` net of the trading fees the bids must also cover.
+fn auction_net_demand(
+    total_bids: u64,
+    fee_basis_points: u16,
+    creator_fee_basis_points: u16,
+) -> u64 {
+    let total_fee_basis_points = fee_basis_points as u128 + creator_fee_basis_points as u128;
+    (total_bids as u128 * 10000 / (10000 + total_fee_basis_points)) as u64
+}
+
+/// Share of a settled auction for a bid of `bid_amount`: the tokens it
+/// receives, the SOL it spent and the SOL refunded to it.
+///
+/// Every bidder pays the same price: their share of the fill is proportional
+/// to their share of the total demand.
+fn auction_claim(
+    tokens_sold: u64,
+    sol_filled: u64,
+    bid_amount: u64,
+    total_bids: u64,
+) -> (u64, u64, u64) {
+    let bid = bid_amount as u128;
+    let total = total_bids as u128;
+    let token_amount = (tokens_sold as u128 * bid / total) as u64;
+    // Round the spent SOL up so refunds can never exceed the unfilled escrow
+    let sol_spent = ((sol_filled as u128 * bid).div_ceil(total) as u64).min(bid_amount);
+    (token_amount, sol_spent, bid_amount - sol_spent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIRTUAL_TOKEN_RESERVES: u64 = 1_073_000_000_000_000;
+    const VIRTUAL_SOL_RESERVES: u64 = 3_000_000_000_000;
+    const REAL_TOKEN_RESERVES: u64 = 793_100_000_000_000;
+
+    fn canonical(platform: SocialPlatform, post_id: &str) -> Option<String> {
+        canonical_post_id(platform, post_id).ok()
+    }
+
+    /// Raw post references and their canonical form, None when rejected.
+    const INSTAGRAM_POST_IDS: &[(&str, Option<&str>)] = &[
+        ("Cx1AbC", Some("Cx1AbC")),
+        ("  Cx1AbC/ ", Some("Cx1AbC")),
+        ("Cx1AbC?igsh=abc", Some("Cx1AbC")),
+        ("https://www.instagram.com/p/Cx1AbC/", Some("Cx1AbC")),
+        ("https://instagram.com/p/Cx1AbC?igsh=a#c", Some("Cx1AbC")),
+        ("http://m.instagram.com/reel/Cabc_-9/", Some("Cabc_-9")),
+        ("instagram.com/reels/Cabc/", Some("Cabc")),
+        ("https://instagram.com/tv/B1tv2/?utm=ig", Some("B1tv2")),
+        ("instagram.com/someuser/p/Cx1AbC/", Some("Cx1AbC")),
+        ("https://instagr.am/p/Cx1AbC", Some("Cx1AbC")),
+        // Hosts, schemes and markers are case-insensitive, IDs are not
+        ("HTTPS://WWW.INSTAGRAM.COM/P/Cx1AbC", Some("Cx1AbC")),
+        ("https://www.Instagram.com/Reel/cXyZ", Some("cXyZ")),
+        // Profiles, foreign hosts and marker-only paths are not posts
+        ("instagram.com/someuser/", None),
+        ("https://www.instagram.com/someuser", None),
+        ("https://evil.com/p/Cx1AbC", None),
+        ("evil.com/instagram.com/p/Cx1AbC", None),
+        ("https://www.instagram.com/p/", None),
+        ("https://www.instagram.com", None),
+        ("instagram.com", None),
+        ("ftp://instagram.com/p/Cx1AbC", None),
+        ("Cx.Yz", None),
+        ("", None),
+    ];
+
+    const TIKTOK_POST_IDS: &[(&str, Option<&str>)] = &[
+        ("7234567890123456789", Some("7234567890123456789")),
+        ("tiktok.com/@user/video/72345?lang=en", Some("72345")),
+        ("https://tiktok.com/@user/photo/72345/", Some("72345")),
+        ("https://www.tiktok.com/@user/video/", None),
+        ("https://www.tiktok.com/@user", None),
+        ("https://vm.tiktok.com/ZMabc/", None),
+        ("https://www.tiktok.com/@user/video/abc", None),
+    ];
+
+    const X_POST_IDS: &[(&str, Option<&str>)] = &[
+        ("1234567890", Some("1234567890")),
+        ("https://x.com/user/status/1234567890", Some("1234567890")),
+        ("https://twitter.com/user/status/123/photo/1", Some("123")),
+        ("https://mobile.twitter.com/user/status/123", None),
+        ("https://x.com/user/status/", None),
+        ("https://x.com/user", None),
+        ("https://evil.com/user/status/123", None),
+    ];
+
+    const YOUTUBE_POST_IDS: &[(&str, Option<&str>)] = &[
+        ("dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+        ("https://www.youtube.com/watch?v=dQw4w9", Some("dQw4w9")),
+        ("https://youtube.com/watch?t=42&v=dQw4w9", Some("dQw4w9")),
+        ("https://m.youtube.com/shorts/abc_-1?si=x", Some("abc_-1")),
+        ("https://youtu.be/dQw4w9WgXcQ?t=42", Some("dQw4w9WgXcQ")),
+        ("https://www.youtube.com/embed/dQw4w9", Some("dQw4w9")),
+        ("https://youtube.com/watch", None),
+        ("https://youtube.com/watch?list=abc", None),
+        ("https://youtube.com/shorts/", None),
+        ("https://youtube.com/@channel", None),
+        ("https://youtu.be/", None),
+    ];
+
+    const GENERIC_URL_POST_IDS: &[(&str, Option<&str>)] = &[
+        ("https://Ex.io/a/b", Some("https://ex.io/a/b")),
+        ("http://www.ex.io/a/b/", Some("https://ex.io/a/b")),
+        ("https://ex.io/a/b/?x=1", Some("https://ex.io/a/b?x=1")),
+        ("https://ex.io/a/b?x=1", Some("https://ex.io/a/b?x=1")),
+        ("https://ex.io/a/b//?x=1#top", Some("https://ex.io/a/b?x=1")),
+        ("https://ex.io/?x=1", Some("https://ex.io?x=1")),
+        ("https://ex.io/a/b?", Some("https://ex.io/a/b")),
+        ("ex.io/a/b", None),
+        ("ftp://ex.io/a/b", None),
+        ("https:///a/b", None),
+        ("https://ex.io/a b", None),
+    ];
+
+    #[test]
+    fn canonical_post_ids() {
+        let platforms = [
+            (SocialPlatform::Instagram, INSTAGRAM_POST_IDS),
+            (SocialPlatform::TikTok, TIKTOK_POST_IDS),
+            (SocialPlatform::X, X_POST_IDS),
+            (SocialPlatform::YouTube, YOUTUBE_POST_IDS),
+            (SocialPlatform::GenericUrl, GENERIC_URL_POST_IDS),
+        ];
+        for (platform, cases) in platforms {
+            for (post_id, expected) in cases {
+                assert_eq!(
+                    canonical(platform, post_id).as_deref(),
+                    *expected,
+                    "{platform:?} {post_id}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn metadata_within_the_limits_is_accepted() {
+        let name = "n".repeat(MAX_NAME_LEN);
+        let symbol = "S".repeat(MAX_SYMBOL_LEN);
+        let uri = format!("https://{}", "u".repeat(MAX_URI_LEN - 8));
+        assert!(validate_metadata(&name, &symbol, &uri).is_ok());
+        assert!(validate_metadata("Pump Coin ✨", "PUMP", "ipfs://bafy").is_ok());
+        assert!(validate_metadata("Pump", "PUMP", "AR://tx").is_ok());
+    }
+
+    #[test]
+    fn metadata_outside_the_limits_is_rejected() {
+        let cases = [
+            ("", "PUMP", "https://a", ProgramError::InvalidName),
+            ("   ", "PUMP", "https://a", ProgramError::InvalidName),
+            ("Pump\nCoin", "PUMP", "https://a", ProgramError::InvalidName),
+            ("Pump", "", "https://a", ProgramError::InvalidSymbol),
+            ("Pump", "PU MP", "https://a", ProgramError::InvalidSymbol),
+            ("Pump", "PUMPÉ", "https://a", ProgramError::InvalidSymbol),
+            ("Pump", "PUMP", "", ProgramError::InvalidUri),
+            ("Pump", "PUMP", "https://", ProgramError::InvalidUri),
+            ("Pump", "PUMP", "http://a", ProgramError::InvalidUri),
+            (
+                "Pump",
+                "PUMP",
+                "javascript:alert(1)",
+                ProgramError::InvalidUri,
+            ),
+            ("Pump", "PUMP", "https://a b", ProgramError::InvalidUri),
+        ];
+        for (name, symbol, uri, error) in cases {
+            assert_eq!(
+                validate_metadata(name, symbol, uri).unwrap_err(),
+                error.into(),
+                "{name:?} {symbol:?} {uri:?}"
+            );
+        }
+
+        // Limits are in bytes, as Metaplex counts them
+        let long_name = "é".repeat(MAX_NAME_LEN / 2 + 1);
+        let long_symbol = "S".repeat(MAX_SYMBOL_LEN + 1);
+        let long_uri = format!("https://{}", "u".repeat(MAX_URI_LEN - 7));
+        let too_long = [
+            (
+                long_name.as_str(),
+                "PUMP",
+                "https://a",
+                ProgramError::InvalidName,
+            ),
+            (
+                "Pump",
+                long_symbol.as_str(),
+                "https://a",
+                ProgramError::InvalidSymbol,
+            ),
+            ("Pump", "PUMP", long_uri.as_str(), ProgramError::InvalidUri),
+        ];
+        for (name, symbol, uri, error) in too_long {
+            assert_eq!(
+                validate_metadata(name, symbol, uri).unwrap_err(),
+                error.into()
+            );
+        }
+    }
+
+    #[test]
+    fn withdraw_leaves_the_curve_rent_exempt() {
+        let minimum_balance = Rent::default().minimum_balance(8 + BondingCurve::LEN);
+        let (first_buyer_share, instapump_share) = (500_000_000, 1_000_000_000);
+        for balance in [
+            0,
+            minimum_balance,
+            minimum_balance + 10000 + first_buyer_share + instapump_share,
+            minimum_balance + 10000 + first_buyer_share + instapump_share + 1,
+            85_000_000_000,
+        ] {
+            let admin_amount =
+                withdraw_sol_amount(balance, minimum_balance, first_buyer_share, instapump_share);
+            if admin_amount == 0 {
+                // withdraw rejects this case before moving any SOL
+                continue;
+            }
+            let remaining = balance - first_buyer_share - instapump_share - admin_amount;
+            assert!(remaining >= minimum_balance);
+            assert!(Rent::default().is_exempt(remaining, 8 + BondingCurve::LEN));
+        }
+    }
+
+    fn graduating_curve(graduation_sol_target: u64) -> BondingCurve {
+        BondingCurve {
+            virtual_token_reserves: 1_000_000_000_000_000,
+            virtual_sol_reserves: 3_000_000_000_000,
+            real_token_reserves: 800_000_000_000_000,
+            graduation_sol_target,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tokens_to_graduation_without_a_target_is_the_token_reserve() {
+        let bonding_curve = graduating_curve(0);
+        assert_eq!(
+            bonding_curve.tokens_to_graduation().unwrap(),
+            bonding_curve.real_token_reserves
+        );
+    }
+
+    #[test]
+    fn tokens_to_graduation_reach_the_sol_target_in_one_buy() {
+        // A buy of everything at once, then one left a lamport short
+        for real_sol_reserves in [0, 84_999_999_999] {
+            let mut bonding_curve = graduating_curve(85_000_000_000);
+            bonding_curve.real_sol_reserves = real_sol_reserves;
+            let token_amount = bonding_curve.tokens_to_graduation().unwrap();
+            let (_, sol_cost, new_virtual_token_reserves, new_virtual_sol_reserves) =
+                calculate_price_and_sol(
+                    token_amount,
+                    bonding_curve.virtual_token_reserves,
+                    bonding_curve.virtual_sol_reserves,
+                    bonding_curve.real_token_reserves,
+                    bonding_curve.real_sol_reserves,
+                )
+                .unwrap();
+            assert!(sol_cost > 0);
+            bonding_curve
+                .apply_buy(
+                    token_amount,
+                    sol_cost,
+                    new_virtual_token_reserves,
+                    new_virtual_sol_reserves,
+                )
+                .unwrap();
+            assert!(bonding_curve.graduation_reached());
+            assert!(bonding_curve.real_token_reserves > 0);
+        }
+    }
+
+    #[test]
+    fn tokens_to_graduation_is_capped_by_the_token_reserve() {
+        // The target is more SOL than the whole reserve sells for
+        let bonding_curve = graduating_curve(u64::MAX / 1_000);
+        assert_eq!(
+            bonding_curve.tokens_to_graduation().unwrap(),
+            bonding_curve.real_token_reserves
+        );
+
+        let mut bonding_curve = graduating_curve(85_000_000_000);
+        bonding_curve.real_token_reserves = 10;
+        assert_eq!(bonding_curve.tokens_to_graduation().unwrap(), 10);
+    }
+
+    /// Serialized size of the Global and BondingCurve fields of the first
+    /// program version, which live accounts still hold.
+    const LEGACY_GLOBAL_LEN: usize = 1 + 32 * 3 + 8 * 4 + 2 + 8 + 2 + 2 + 8 + 8;
+    const LEGACY_BONDING_CURVE_LEN: usize = 32 + 8 * 5 + 1 + 32 + 32;
+
+    /// An account of the first program version after grow_account: its old
+    /// fields followed by zeros.
+    fn legacy_account<T: AccountSerialize>(account: &T, legacy_len: usize, len: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data.truncate(8 + legacy_len);
+        data.resize(8 + len, 0);
+        data
+    }
+
+    #[test]
+    fn legacy_global_grows_into_the_current_layout() {
+        let legacy = Global {
+            initialized: true,
+            authority: Pubkey::new_unique(),
+            fee_recipient: Pubkey::new_unique(),
+            fee_basis_points: 100,
+            sol_share_instapump_after_raydium: 42,
+            ..Default::default()
+        };
+        let data = legacy_account(&legacy, LEGACY_GLOBAL_LEN, Global::LEN);
+        let global = Global::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(global.authority, legacy.authority);
+        assert_eq!(global.fee_recipient, legacy.fee_recipient);
+        assert_eq!(global.fee_basis_points, 100);
+        assert_eq!(global.sol_share_instapump_after_raydium, 42);
+        assert_eq!(global.version, 0);
+        assert_eq!(global.collection_mint, Pubkey::default());
+        assert!(!global.platforms.iter().any(|platform| platform.enabled));
+    }
+
+    #[test]
+    fn legacy_bonding_curve_grows_into_the_current_layout() {
+        let legacy = BondingCurve {
+            mint: Pubkey::new_unique(),
+            virtual_token_reserves: 1_073_000_000_000_000,
+            real_sol_reserves: 7,
+            complete: true,
+            creator_address: Pubkey::new_unique(),
+            first_buyer_address: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        let data = legacy_account(&legacy, LEGACY_BONDING_CURVE_LEN, BondingCurve::LEN);
+        let bonding_curve = BondingCurve::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(bonding_curve.mint, legacy.mint);
+        assert_eq!(bonding_curve.real_sol_reserves, 7);
+        assert!(bonding_curve.complete);
+        assert_eq!(
+            bonding_curve.first_buyer_address,
+            legacy.first_buyer_address
+        );
+        assert_eq!(bonding_curve.phase, CurvePhase::Trading);
+        assert_eq!(bonding_curve.version, 0);
+        assert_eq!(bonding_curve.direct_launch_escrow, 0);
+    }
+
+    #[test]
+    fn auction_net_demand_covers_fees() {
+        for total_bids in [1, 999, 1_000_000, 7_777_777_777, 500_000_000_000] {
+            for (fee_basis_points, creator_fee_basis_points) in
+                [(0, 0), (100, 0), (100, 50), (250, 125)]
+            {
+                let net_demand =
+                    auction_net_demand(total_bids, fee_basis_points, creator_fee_basis_points);
+                let fees = net_demand * fee_basis_points as u64 / 10000
+                    + net_demand * creator_fee_basis_points as u64 / 10000;
+                assert!(net_demand + fees <= total_bids);
+            }
+        }
+    }
+
+    #[test]
+    fn auction_settlement_fill_stays_within_bids() {
+        let (fee_basis_points, creator_fee_basis_points) = (100, 50);
+        for total_bids in [1_000_000, 250_000_000, 3_000_000_000, 40_000_000_000] {
+            let net_demand =
+                auction_net_demand(total_bids, fee_basis_points, creator_fee_basis_points);
+            let tokens =
+                calculate_tokens_for_sol(net_demand, VIRTUAL_TOKEN_RESERVES, VIRTUAL_SOL_RESERVES)
+                    .unwrap()
+                    .min(REAL_TOKEN_RESERVES);
+            let (_, sol_cost, _, _) = calculate_price_and_sol(
+                tokens,
+                VIRTUAL_TOKEN_RESERVES,
+                VIRTUAL_SOL_RESERVES,
+                REAL_TOKEN_RESERVES,
+                0,
+            )
+            .unwrap();
+            let sol_filled = sol_cost
+                + sol_cost * fee_basis_points as u64 / 10000
+                + sol_cost * creator_fee_basis_points as u64 / 10000;
+            assert!(sol_cost <= net_demand);
+            assert!(sol_filled <= total_bids);
+        }
+    }
+
+    #[test]
+    fn auction_claims_are_pro_rata() {
+        let bids = [1_000_000_000, 3_000_000_000, 6_000_000_000];
+        let total_bids: u64 = bids.iter().sum();
+        let (tokens_sold, sol_filled) = (50_000_000_000, 5_000_000_000);
+        for bid in bids {
+            let (token_amount, sol_spent, sol_refund) =
+                auction_claim(tokens_sold, sol_filled, bid, total_bids);
+            // Bids are whole tenths of the demand, so the shares are exact
+            let tenths = bid / 1_000_000_000;
+            assert_eq!(token_amount, tokens_sold / 10 * tenths);
+            assert_eq!(sol_spent, sol_filled / 10 * tenths);
+            assert_eq!(sol_spent + sol_refund, bid);
+        }
+    }
+
+    #[test]
+    fn auction_refunds_never_exceed_escrow() {
+        let bids = [1, 7, 333_333_333, 999_999_999, 1_234_567_891];
+        let total_bids: u64 = bids.iter().sum();
+        for sol_filled in [0, 1, total_bids / 3, total_bids - 1, total_bids] {
+            let tokens_sold = 123_456_789_012;
+            let (mut tokens, mut refunds) = (0, 0);
+            for bid in bids {
+                let (token_amount, _, sol_refund) =
+                    auction_claim(tokens_sold, sol_filled, bid, total_bids);
+                tokens += token_amount;
+                refunds += sol_refund;
+            }
+            assert!(tokens <= tokens_sold);
+            assert!(refunds <= total_bids - sol_filled);
+        }
+    }
+
+    #[test]
+    fn price_history_cap_fits_a_cpi_allocation() {
+        use solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE;
+
+        let space = 8 + PriceHistory::space(MAX_PRICE_HISTORY_CANDLES, 0);
+        assert!(space <= MAX_PERMITTED_DATA_INCREASE);
+        assert!(PriceHistory::check_capacity(MAX_PRICE_HISTORY_CANDLES / 2, 0).is_ok());
+        assert!(PriceHistory::check_capacity(MAX_PRICE_HISTORY_CANDLES, 1).is_err());
+        assert!(PriceHistory::check_capacity(u32::MAX, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn user_position_average_cost_survives_partial_sells() {
+        let mut position = UserPosition::default();
+        position.record_buy(1_000, 1_000, 10, 1).unwrap();
+        assert_eq!(position.cost_basis, 1_010);
+        assert_eq!(position.average_cost_basis(), 1_010_000);
+
+        // A quarter of the balance releases a quarter of the cost, rounded down
+        position.record_sell(250, 400, 4, 2).unwrap();
+        assert_eq!(position.token_balance, 750);
+        assert_eq!(position.cost_basis, 758);
+        assert_eq!(position.realized_pnl, 144);
+        assert_eq!(position.average_cost_basis(), 1_010_666);
+
+        position.record_buy(250, 500, 5, 3).unwrap();
+        assert_eq!(position.cost_basis, 1_263);
+        assert_eq!(position.average_cost_basis(), 1_263_000);
+    }
+
+    #[test]
+    fn user_position_realized_pnl_has_the_right_sign() {
+        let mut position = UserPosition::default();
+        position.record_buy(3, 1_000, 10, 1).unwrap();
+        position.record_sell(1, 400, 4, 2).unwrap();
+        assert_eq!(position.realized_pnl, 400 - 4 - 336);
+        position.record_sell(2, 300, 3, 3).unwrap();
+        assert_eq!(position.realized_pnl, 60 + 300 - 3 - 674);
+
+        // Rounding never loses cost: a full exit realizes proceeds minus cost
+        assert_eq!(position.cost_basis, 0);
+        assert_eq!(position.token_balance, 0);
+        assert_eq!(position.average_cost_basis(), 0);
+        assert_eq!(position.realized_pnl, (400 + 300 - 4 - 3) - 1_010);
+        assert_eq!((position.first_trade_at, position.last_trade_at), (1, 3));
+    }
+
+    #[test]
+    fn user_position_sells_beyond_the_tracked_balance_release_all_cost() {
+        let mut position = UserPosition::default();
+        position.record_buy(100, 1_000, 0, 1).unwrap();
+        // Tokens received outside the curve are sold at no cost
+        position.record_sell(150, 1_800, 0, 2).unwrap();
+        assert_eq!(position.token_balance, 0);
+        assert_eq!(position.cost_basis, 0);
+        assert_eq!(position.realized_pnl, 800);
+    }
+
+    /// ed25519 program instruction data for one signature of `message` by
+    /// `public_key`, with everything inline. The signature itself is checked by
+    /// the runtime, not by the program, so it is left zeroed.
+    fn ed25519_instruction_data(public_key: &Pubkey, message: &[u8]) -> Vec<u8> {
+        let (public_key_offset, signature_offset, message_offset) = (16, 48, 112);
+        let mut data = vec![1, 0];
+        for value in [
+            signature_offset,
+            u16::MAX,
+            public_key_offset,
+            u16::MAX,
+            message_offset,
+            message.len() as u16,
+            u16::MAX,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(public_key.as_ref());
+        data.extend_from_slice(&[0; 64]);
+        data.extend_from_slice(message);
+        data
+    }
+
+    /// Runs verify_attestation as the last of `instructions`.
+    fn verify_in_transaction(
+        instructions: &[(Pubkey, Vec<u8>)],
+        attesters: &[Pubkey],
+        message: &[u8],
+    ) -> Result<()> {
+        use solana_program::sysvar::instructions::{
+            construct_instructions_data, store_current_index, BorrowedInstruction,
+        };
+
+        let borrowed: Vec<_> = instructions
+            .iter()
+            .map(|(program_id, data)| BorrowedInstruction {
+                program_id,
+                accounts: vec![],
+                data,
+            })
+            .collect();
+        let mut data = construct_instructions_data(&borrowed);
+        store_current_index(&mut data, instructions.len() as u16 - 1);
+        let (key, owner, mut lamports) = (instructions_sysvar::ID, Pubkey::default(), 0);
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        verify_attestation(&account, attesters, message)
+    }
+
+    #[test]
+    fn attestation_must_be_signed_by_an_attester_for_this_launch() {
+        let attester = Pubkey::new_unique();
+        let attesters = [Pubkey::default(), attester];
+        let (creator, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let post_id_hash = hash_post_id("Cx1AbC");
+        let message = attestation_message(
+            SocialPlatform::Instagram,
+            &post_id_hash,
+            &creator,
+            &mint,
+            99,
+        );
+        let signed_by = |signer: &Pubkey, message: &[u8]| {
+            vec![
+                (
+                    ed25519_program::ID,
+                    ed25519_instruction_data(signer, message),
+                ),
+                (crate::ID, vec![]),
+            ]
+        };
+
+        assert!(
+            verify_in_transaction(&signed_by(&attester, &message), &attesters, &message).is_ok()
+        );
+
+        let wrong_signer = signed_by(&Pubkey::new_unique(), &message);
+        assert_eq!(
+            verify_in_transaction(&wrong_signer, &attesters, &message).unwrap_err(),
+            ProgramError::UnknownAttester.into()
+        );
+        // An empty attester slot is never a valid signer
+        let empty_slot = signed_by(&Pubkey::default(), &message);
+        assert_eq!(
+            verify_in_transaction(&empty_slot, &attesters, &message).unwrap_err(),
+            ProgramError::UnknownAttester.into()
+        );
+
+        // The same attestation can't launch another mint
+        let other_mint = Pubkey::new_unique();
+        let replayed = attestation_message(
+            SocialPlatform::Instagram,
+            &post_id_hash,
+            &creator,
+            &other_mint,
+            99,
+        );
+        assert_eq!(
+            verify_in_transaction(&signed_by(&attester, &message), &attesters, &replayed)
+                .unwrap_err(),
+            ProgramError::InvalidAttestation.into()
+        );
+        let wrong_message = signed_by(&attester, &message[..message.len() - 1]);
+        assert_eq!(
+            verify_in_transaction(&wrong_message, &attesters, &message).unwrap_err(),
+            ProgramError::InvalidAttestation.into()
+        );
+    }
+
+    #[test]
+    fn attestation_must_come_right_before_create() {
+        let attester = Pubkey::new_unique();
+        let message = b"attestation".to_vec();
+        let ed25519 = (
+            ed25519_program::ID,
+            ed25519_instruction_data(&attester, &message),
+        );
+        let create = (crate::ID, vec![]);
+
+        for instructions in [
+            vec![create.clone()],
+            vec![(crate::ID, ed25519.1.clone()), create.clone()],
+            vec![ed25519.clone(), (crate::ID, vec![]), create.clone()],
+        ] {
+            assert_eq!(
+                verify_in_transaction(&instructions, &[attester], &message).unwrap_err(),
+                ProgramError::MissingAttestation.into()
+            );
+        }
+        assert!(verify_in_transaction(&[ed25519, create], &[attester], &message).is_ok());
+    }
+
+    fn candle_starts(buffer: &CandleBuffer) -> Vec<i64> {
+        buffer
+            .candles
+            .iter()
+            .map(|candle| candle.start_time)
+            .collect()
+    }
+
+    #[test]
+    fn candle_buffer_aggregates_trades_within_a_period() {
+        let mut buffer = CandleBuffer {
+            capacity: 3,
+            ..Default::default()
+        };
+        buffer.record_trade(60, 130, 100, 110, 5, 50);
+        buffer.record_trade(60, 150, 110, 90, 3, 30);
+        buffer.record_trade(60, 179, 90, 95, 1, 10);
+        assert_eq!(buffer.candles.len(), 1);
+        let candle = buffer.candles[0];
+        assert_eq!(candle.start_time, 120);
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (100, 110, 90, 95)
+        );
+        assert_eq!((candle.volume_sol, candle.volume_tokens), (9, 90));
+
+        // The next period opens a new candle
+        buffer.record_trade(60, 180, 95, 97, 1, 10);
+        assert_eq!(candle_starts(&buffer), [120, 180]);
+        assert_eq!(buffer.candles[1].open, 95);
+    }
+
+    #[test]
+    fn candle_buffer_rolls_over_the_oldest_candle() {
+        let mut buffer = CandleBuffer {
+            capacity: 3,
+            ..Default::default()
+        };
+        for timestamp in [0, 60, 120, 180, 240] {
+            buffer.record_trade(60, timestamp, 100, 100, 1, 1);
+        }
+        assert_eq!(candle_starts(&buffer), [180, 240, 120]);
+        assert_eq!(buffer.head, 2);
+
+        // A trade in the latest period updates it in place
+        buffer.record_trade(60, 250, 100, 120, 1, 1);
+        assert_eq!(candle_starts(&buffer), [180, 240, 120]);
+        assert_eq!(buffer.candles[1].high, 120);
+        assert_eq!(buffer.candles[1].volume_sol, 2);
+    }
+
+    #[test]
+    fn candle_buffer_grows_in_order() {
+        let mut buffer = CandleBuffer {
+            capacity: 3,
+            ..Default::default()
+        };
+        for timestamp in [0, 60, 120, 180, 240] {
+            buffer.record_trade(60, timestamp, 100, 100, 1, 1);
+        }
+        buffer.grow(5).unwrap();
+        assert_eq!(candle_starts(&buffer), [120, 180, 240]);
+        assert_eq!(buffer.head, 0);
+
+        for timestamp in [300, 360, 420] {
+            buffer.record_trade(60, timestamp, 100, 100, 1, 1);
+        }
+        assert_eq!(candle_starts(&buffer), [420, 180, 240, 300, 360]);
+        assert_eq!(buffer.head, 1);
+        assert!(buffer.grow(4).is_err());
+    }
+
+    fn curve_with_price(virtual_sol_reserves: u64) -> BondingCurve {
+        BondingCurve {
+            virtual_token_reserves: 1_000_000_000_000_000,
+            virtual_sol_reserves,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn price_accumulator_integrates_the_spot_price() {
+        let mut bonding_curve = curve_with_price(3_000_000_000_000);
+        assert_eq!(bonding_curve.spot_price(), 30);
+        bonding_curve.start_price_accumulator(1_000);
+
+        bonding_curve.update_price_accumulator(1_030).unwrap();
+        assert_eq!(bonding_curve.price_cumulative, 30 * 30);
+        // Observations are at least an interval apart
+        assert_eq!(bonding_curve.observation_index, 0);
+
+        bonding_curve.update_price_accumulator(1_060).unwrap();
+        assert_eq!(bonding_curve.price_cumulative, 30 * 60);
+        assert_eq!(bonding_curve.observation_index, 1);
+        assert_eq!(bonding_curve.observations[1].timestamp, 1_060);
+        assert_eq!(bonding_curve.observations[1].price_cumulative, 30 * 60);
+
+        // A clock behind the last update adds nothing
+        bonding_curve.update_price_accumulator(1_050).unwrap();
+        assert_eq!(bonding_curve.price_cumulative, 30 * 60);
+    }
+
+    #[test]
+    fn price_accumulator_wraps_its_observations() {
+        let mut bonding_curve = curve_with_price(3_000_000_000_000);
+        bonding_curve.start_price_accumulator(1_000);
+        for step in 1..=TWAP_OBSERVATIONS as i64 {
+            bonding_curve
+                .update_price_accumulator(1_000 + step * TWAP_OBSERVATION_INTERVAL)
+                .unwrap();
+        }
+        assert_eq!(bonding_curve.observation_index, 0);
+        let newest = 1_000 + TWAP_OBSERVATIONS as i64 * TWAP_OBSERVATION_INTERVAL;
+        assert_eq!(bonding_curve.observations[0].timestamp, newest);
+    }
+
+    #[test]
+    fn twap_averages_over_the_window() {
+        let mut bonding_curve = curve_with_price(3_000_000_000_000);
+        bonding_curve.start_price_accumulator(1_000);
+        bonding_curve.update_price_accumulator(1_060).unwrap();
+        bonding_curve.virtual_sol_reserves = 6_000_000_000_000;
+        assert_eq!(bonding_curve.spot_price(), 60);
+
+        // The price since the last update counts up to `now`
+        assert_eq!(bonding_curve.twap(120, 1_120).unwrap(), 45);
+        assert_eq!(bonding_curve.twap(60, 1_120).unwrap(), 60);
+        // Windows reach back to the newest observation that covers them
+        assert_eq!(bonding_curve.twap(90, 1_120).unwrap(), 45);
+    }
+
+    #[test]
+    fn twap_needs_a_window_and_history() {
+        let bonding_curve = BondingCurve::default();
+        assert_eq!(
+            bonding_curve.twap(60, 1_000).unwrap_err(),
+            ProgramError::InsufficientPriceHistory.into()
+        );
+
+        let mut bonding_curve = curve_with_price(3_000_000_000_000);
+        bonding_curve.start_price_accumulator(1_000);
+        assert_eq!(
+            bonding_curve.twap(0, 1_000).unwrap_err(),
+            ProgramError::InvalidTwapWindow.into()
+        );
+        assert_eq!(
+            bonding_curve.twap(0, 2_000).unwrap_err(),
+            ProgramError::InvalidTwapWindow.into()
+        );
+        // No observation is old enough to cover the window yet
+        assert_eq!(
+            bonding_curve.twap(60, 1_030).unwrap_err(),
+            ProgramError::InsufficientPriceHistory.into()
+        );
+        assert_eq!(bonding_curve.twap(60, 1_060).unwrap(), 30);
+    }
+
+    #[test]
+    fn launch_window_fee_decays_linearly() {
+        let global = Global {
+            launch_window_duration: 60,
+            launch_window_fee_basis_points: 3000,
+            ..Default::default()
+        };
+        let sol_cost = 1_000_000_000;
+        assert_eq!(global.launch_window_fee(sol_cost, 60), 300_000_000);
+        assert_eq!(global.launch_window_fee(sol_cost, 30), 150_000_000);
+        assert_eq!(global.launch_window_fee(sol_cost, 1), 5_000_000);
+        assert_eq!(global.launch_window_fee(sol_cost, 0), 0);
+        let mut previous = u64::MAX;
+        for remaining in (0..=60).rev() {
+            let fee = global.launch_window_fee(sol_cost, remaining);
+            assert!(fee <= previous);
+            previous = fee;
+        }
+    }
+
+    #[test]
+    fn launch_window_closes_after_its_duration() {
+        let mut global = Global {
+            launch_window_duration: 60,
+            ..Default::default()
+        };
+        let bonding_curve = BondingCurve {
+            created_slot: 1_000,
+            created_at: 1_700_000_000,
+            ..Default::default()
+        };
+        let at = |slot, unix_timestamp| Clock {
+            slot,
+            unix_timestamp,
+            ..Default::default()
+        };
+        let remaining =
+            |global: &Global, clock| bonding_curve.launch_window_remaining(global, &clock);
+        assert_eq!(remaining(&global, at(0, 1_700_000_000)), Some(60));
+        assert_eq!(remaining(&global, at(0, 1_700_000_059)), Some(1));
+        assert_eq!(remaining(&global, at(0, 1_700_000_060)), None);
+        // A clock behind the launch counts as the start of the window
+        assert_eq!(remaining(&global, at(0, 1_699_999_000)), Some(60));
+
+        global.launch_window_in_slots = true;
+        assert_eq!(remaining(&global, at(1_010, 0)), Some(50));
+        assert_eq!(remaining(&global, at(1_060, 0)), None);
+
+        global.launch_window_duration = 0;
+        assert_eq!(remaining(&global, at(1_000, 1_700_000_000)), None);
+    }
+
+    #[test]
+    fn referral_fee_is_a_tier_share_of_the_admin_fee() {
+        let global = Global {
+            referral_tier_basis_points: [0, 1000, 2500, 10000],
+            ..Default::default()
+        };
+        assert_eq!(global.referral_fee(1_000_000, 0), 0);
+        assert_eq!(global.referral_fee(1_000_000, 1), 100_000);
+        assert_eq!(global.referral_fee(1_000_000, 2), 250_000);
+        assert_eq!(global.referral_fee(1_000_000, 3), 1_000_000);
+        // Shares round down, and a tier past the table earns nothing
+        assert_eq!(global.referral_fee(9, 1), 0);
+        assert_eq!(global.referral_fee(39, 2), 9);
+        assert_eq!(global.referral_fee(1_000_000, REFERRAL_TIERS as u8), 0);
+        for admin_fee in [0, 1, 12_345, u64::MAX] {
+            for tier in 0..REFERRAL_TIERS as u8 {
+                assert!(global.referral_fee(admin_fee, tier) <= admin_fee);
+            }
+        }
+    }
+}